@@ -10,7 +10,7 @@ const C_WIDTH: u32 = 800;
 const C_HEIGHT: u32 = C_WIDTH * 3 / 4;
 
 const TRAVEL: f64 = 10.;
-const ROTATE: f64 = 0.5235987756;
+const ROTATE: f64 = std::f64::consts::FRAC_PI_6;
 const DCOLOR: (f64, f64, f64) = (81., 0., 0.);
 const ICOLOR: (f64, f64, f64) = (77., 38., 91.);
 
@@ -40,7 +40,7 @@ pub fn main() {
 
     let turtle = CanvasContextTurtle::new(
         context,
-        -1.5707963268,
+        -std::f64::consts::FRAC_PI_2,
         (canvas.width() as f64 / 2., canvas.height() as f64),
         1.,
         (0., 0., 0.),
@@ -68,8 +68,6 @@ pub fn main() {
     });
 
     system.step_by(4);
-    unsafe {
-        console::debug_1(&JsValue::from_str(&system.axiom));
-    }
+    console::debug_1(&JsValue::from_str(&system.axiom.to_string()));
     executor.execute(&system).unwrap();
 }
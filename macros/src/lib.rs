@@ -0,0 +1,244 @@
+//! The `lsystem!` macro of the `l-system` crate, which checks a grammar as it
+//! expands so that mistakes are reported at the tokens they're in.
+
+extern crate proc_macro;
+
+use l_system_syntax::expr::{Expr, ParseError, Parser};
+use l_system_syntax::rule::{check_weights, modules, Rule, WeightError};
+use proc_macro::{Delimiter, Spacing, Span, TokenStream, TokenTree};
+
+// Documented where `l-system` re-exports it.
+#[proc_macro]
+pub fn lsystem(input: TokenStream) -> TokenStream {
+    let tokens: Vec<TokenTree> = input.into_iter().collect();
+    let mut grammar = Grammar::default();
+    let result = tokens
+        .split(|t| is_punct(t, ';'))
+        .filter(|statement| !statement.is_empty())
+        .try_for_each(|statement| grammar.statement(statement));
+    let result = result
+        .and_then(|()| match grammar.axiom {
+            true => Ok(()),
+            false => Err(Error::new(Span::call_site(), "missing `axiom`")),
+        })
+        .and_then(|()| grammar.check_weights());
+
+    match result {
+        Ok(()) => format!("::l_system::macros::build(&[{}])", grammar.items.join(", "))
+            .parse()
+            .unwrap(),
+        Err(error) => error.into_compile_error(),
+    }
+}
+
+struct Error {
+    span: Span,
+    message: String,
+}
+
+impl Error {
+    fn new(span: Span, message: impl Into<String>) -> Self {
+        Self {
+            span,
+            message: message.into(),
+        }
+    }
+
+    fn into_compile_error(self) -> TokenStream {
+        let tokens: TokenStream = format!("::core::compile_error!({:?})", self.message)
+            .parse()
+            .unwrap();
+        tokens
+            .into_iter()
+            .map(|mut token| {
+                token.set_span(self.span);
+                token
+            })
+            .collect()
+    }
+}
+
+// The text of some tokens as the `.lsys` format reads it, without whitespace,
+// along with where each token starts so errors can point at it.
+struct Text {
+    text: String,
+    starts: Vec<(usize, Span)>,
+    // Where errors at the end of the text point.
+    end: Span,
+}
+
+impl Text {
+    fn new(tokens: &[TokenTree], end: Span) -> Self {
+        let mut text = Self {
+            text: String::new(),
+            starts: vec![],
+            end,
+        };
+        tokens.iter().for_each(|token| text.push(token));
+        text
+    }
+
+    fn push(&mut self, token: &TokenTree) {
+        let TokenTree::Group(group) = token else {
+            self.push_str(&token.to_string(), token.span());
+            return;
+        };
+        let (open, close) = match group.delimiter() {
+            Delimiter::Parenthesis => ("(", ")"),
+            Delimiter::Bracket => ("[", "]"),
+            Delimiter::Brace => ("{", "}"),
+            Delimiter::None => ("", ""),
+        };
+        self.push_str(open, group.span_open());
+        group
+            .stream()
+            .into_iter()
+            .for_each(|token| self.push(&token));
+        self.push_str(close, group.span_close());
+    }
+
+    fn push_str(&mut self, text: &str, span: Span) {
+        if !text.is_empty() {
+            self.starts.push((self.text.len(), span));
+            self.text += text;
+        }
+    }
+
+    fn span(&self, position: usize) -> Span {
+        match self.starts.iter().rev().find(|(s, _)| *s <= position) {
+            Some((_, span)) if position < self.text.len() => *span,
+            _ => self.end,
+        }
+    }
+
+    fn error(&self, error: ParseError) -> Error {
+        Error::new(self.span(error.position), error.message)
+    }
+}
+
+#[derive(Default)]
+struct Grammar {
+    names: Vec<String>,
+    values: Vec<f64>,
+    axiom: bool,
+    // The productions with their weights and where those are, to check the
+    // weights of each stochastic production together.
+    rules: Vec<(Rule, f64, Span)>,
+    // The expressions that make up the statements for `macros::build`.
+    items: Vec<String>,
+}
+
+impl Grammar {
+    fn statement(&mut self, tokens: &[TokenTree]) -> Result<(), Error> {
+        // Statements end in `;` or the end of the macro, which errors at the
+        // end of a statement point to.
+        let end = tokens.last().unwrap().span();
+        match tokens {
+            [TokenTree::Ident(keyword), rest @ ..] if keyword.to_string() == "const" => {
+                self.constant(keyword.span(), rest, end)
+            }
+            [TokenTree::Ident(keyword), colon, rest @ ..]
+                if keyword.to_string() == "axiom" && is_punct(colon, ':') =>
+            {
+                let axiom = Text::new(rest, end);
+                let mut parser = Parser::new(&axiom.text);
+                modules(&mut parser, &self.names).map_err(|e| axiom.error(e))?;
+                self.axiom = true;
+                self.items
+                    .push(format!("::l_system::macros::Item::Axiom({:?})", axiom.text));
+                Ok(())
+            }
+            _ => self.production(tokens, end),
+        }
+    }
+
+    fn constant(&mut self, keyword: Span, tokens: &[TokenTree], end: Span) -> Result<(), Error> {
+        let name = match tokens.first() {
+            Some(TokenTree::Ident(name)) => name,
+            Some(token) => return Err(Error::new(token.span(), "expected a constant name")),
+            None => return Err(Error::new(keyword, "expected a constant name")),
+        };
+        match tokens.get(1) {
+            Some(equals) if is_punct(equals, '=') => {}
+            Some(token) => return Err(Error::new(token.span(), "expected `=`")),
+            None => return Err(Error::new(name.span(), "expected `=` after the name")),
+        }
+
+        let value = Text::new(&tokens[2..], end);
+        let expr = Expr::parse(&value.text, &self.names).map_err(|e| value.error(e))?;
+        let name_text = name.to_string();
+        if self.names.contains(&name_text) {
+            let message = format!("`{}` is already defined", name_text);
+            return Err(Error::new(name.span(), message));
+        }
+        self.values.push(expr.eval(&self.values));
+        self.items.push(format!(
+            "::l_system::macros::Item::Constant({:?}, {:?})",
+            name_text, value.text
+        ));
+        self.names.push(name_text);
+        Ok(())
+    }
+
+    // Checks `predecessor : guard -> successor : weight`, where the guard and
+    // weight are optional, the way the `.lsys` format reads it.
+    fn production(&mut self, tokens: &[TokenTree], end: Span) -> Result<(), Error> {
+        // The text has no whitespace, so `- >` would read as an arrow too.
+        if !(1..tokens.len()).any(|i| is_arrow(&tokens[i - 1..=i])) {
+            let message = "expected `->` in the production";
+            return Err(Error::new(tokens[0].span(), message));
+        }
+        let text = Text::new(tokens, end);
+        let rule = Rule::parse(&text.text, &self.names, true).map_err(|e| text.error(e))?;
+
+        let (weight, span) = match &rule.weight {
+            Some(weight) => {
+                let colon = text.text.rfind(':').unwrap();
+                (weight.eval(&self.values), text.span(colon + 1))
+            }
+            None => (1., tokens[0].span()),
+        };
+        self.rules.push((rule, weight, span));
+        self.items.push(format!(
+            "::l_system::macros::Item::Production({:?})",
+            text.text
+        ));
+        Ok(())
+    }
+
+    // Checks the weights of stochastic productions as `lsys` groups them,
+    // pointing at the weight that is wrong or at the first one of the group.
+    fn check_weights(&self) -> Result<(), Error> {
+        let mut rules = self.rules.iter().peekable();
+        while let Some((first, weight, span)) = rules.next() {
+            let mut group = vec![(*weight, *span)];
+            while let Some((_, weight, span)) = rules.next_if(|(rule, ..)| rule.continues(first)) {
+                group.push((*weight, *span));
+            }
+            if let Err(error) = check_weights(group.iter().map(|(weight, _)| *weight)) {
+                let span = match error {
+                    WeightError::Invalid(_) => group
+                        .iter()
+                        .find(|(weight, _)| weight.is_nan() || *weight < 0.)
+                        .map_or(*span, |(_, span)| *span),
+                    WeightError::ZeroTotal => *span,
+                };
+                return Err(Error::new(span, error.to_string()));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_punct(token: &TokenTree, c: char) -> bool {
+    matches!(token, TokenTree::Punct(p) if p.as_char() == c)
+}
+
+fn is_arrow(tokens: &[TokenTree]) -> bool {
+    match tokens {
+        [TokenTree::Punct(minus), greater] => {
+            minus.as_char() == '-' && minus.spacing() == Spacing::Joint && is_punct(greater, '>')
+        }
+        _ => false,
+    }
+}
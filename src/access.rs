@@ -0,0 +1,119 @@
+use crate::analysis::AnalysisError;
+use crate::word::{Module, Symbol, Word};
+use crate::LSystem;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Random access into a later generation of a deterministic context-free
+/// system, without expanding the word. Queries descend through the derivation
+/// tree, skipping whole subtrees by their precomputed lengths.
+///
+/// Lengths saturate at `u128::MAX`, past which positions can't be told apart.
+pub struct RandomAccess {
+    axiom: Word,
+    successors: HashMap<Symbol, Word>,
+    /// `lengths[k][s]` is the length of what symbol `s` becomes after `k` steps.
+    lengths: Vec<HashMap<Symbol, u128>>,
+}
+
+impl LSystem {
+    /// Prepares random access into the word `step_by(n)` would produce, see
+    /// [`LSystem::successor_map`] for which systems are supported.
+    pub fn random_access(&self, n: usize) -> Result<RandomAccess, AnalysisError> {
+        let successors = self.successor_map()?;
+
+        let mut lengths = Vec::with_capacity(n + 1);
+        lengths.push(successors.keys().map(|s| (*s, 1)).collect());
+        for k in 1..=n {
+            let previous: &HashMap<Symbol, u128> = &lengths[k - 1];
+            let next = successors
+                .iter()
+                .map(|(symbol, word)| {
+                    let len = word
+                        .iter()
+                        .fold(0u128, |len, m| len.saturating_add(previous[&m.symbol]));
+                    (*symbol, len)
+                })
+                .collect();
+            lengths.push(next);
+        }
+
+        Ok(RandomAccess {
+            axiom: self.axiom.clone(),
+            successors,
+            lengths,
+        })
+    }
+}
+
+impl RandomAccess {
+    fn depth(&self) -> usize {
+        self.lengths.len() - 1
+    }
+
+    fn length(&self, level: usize, module: &Module) -> u128 {
+        self.lengths[level][&module.symbol]
+    }
+
+    /// The number of modules in the generation.
+    pub fn len(&self) -> u128 {
+        let depth = self.depth();
+        self.axiom
+            .iter()
+            .fold(0, |len, m| len.saturating_add(self.length(depth, m)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The module at `index`.
+    pub fn get(&self, mut index: u128) -> Option<Module> {
+        let mut word = &self.axiom;
+        for level in (0..=self.depth()).rev() {
+            let mut modules = word.iter();
+            let module = loop {
+                let module = modules.next()?;
+                let len = self.length(level, module);
+                if index < len {
+                    break module;
+                }
+                index -= len;
+            };
+
+            if level == 0 {
+                return Some(module.clone());
+            }
+            word = &self.successors[&module.symbol];
+        }
+        None
+    }
+
+    /// The modules within `range`, clamped to the end of the generation.
+    pub fn slice(&self, range: Range<u128>) -> Word {
+        let mut slice = Word::new();
+        self.collect(&self.axiom, self.depth(), range, &mut slice);
+        slice
+    }
+
+    // Appends the modules of `word` expanded `level` times that fall within
+    // `range`, which is relative to the start of `word`.
+    fn collect(&self, word: &Word, level: usize, range: Range<u128>, slice: &mut Word) {
+        let mut start = 0u128;
+        for module in word.iter() {
+            if start >= range.end {
+                break;
+            }
+            let end = start.saturating_add(self.length(level, module));
+            if end > range.start {
+                if level == 0 {
+                    slice.push(module.clone());
+                } else {
+                    let inner = range.start.saturating_sub(start)..range.end - start;
+                    self.collect(&self.successors[&module.symbol], level - 1, inner, slice);
+                }
+            }
+            start = end;
+        }
+    }
+}
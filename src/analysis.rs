@@ -0,0 +1,273 @@
+use crate::word::{Module, Symbol, Word};
+use crate::{LSystem, Match};
+use num_bigint::BigUint;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AnalysisError {
+    /// The token spans more than one module.
+    MultiModuleToken(String),
+    ContextSensitive(String),
+    Stochastic(String),
+    Parametric(String),
+    /// The system has decomposition rules, which the analysis can't follow.
+    Decomposition,
+    /// The system picks a production table with `table` or a schedule.
+    Tables,
+    /// The cut symbol can occur in the word, dropping parts of it.
+    Cut,
+}
+
+impl fmt::Display for AnalysisError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MultiModuleToken(t) => write!(f, "the token `{}` spans several modules", t),
+            Self::ContextSensitive(t) => write!(f, "the production for `{}` has a context", t),
+            Self::Stochastic(t) => write!(f, "the production for `{}` is stochastic", t),
+            Self::Parametric(t) => write!(f, "the production for `{}` is parametric", t),
+            Self::Decomposition => f.write_str("the system has decomposition rules"),
+            Self::Tables => f.write_str("the system uses production tables"),
+            Self::Cut => f.write_str("the cut symbol can occur in the word"),
+        }
+    }
+}
+
+impl std::error::Error for AnalysisError {}
+
+impl LSystem {
+    /// The successor of every symbol reachable from the axiom, for systems whose
+    /// productions are deterministic and context-free. Successor closures are
+    /// called once per symbol and assumed to always give the same replacement.
+    /// Symbols without a production are their own successor. Systems with
+    /// decomposition rules, that pick a table other than `production_rules` or
+    /// that can produce the cut symbol aren't supported.
+    pub fn successor_map(&self) -> Result<HashMap<Symbol, Word>, AnalysisError> {
+        if !self.decomposition_rules.is_empty() {
+            return Err(AnalysisError::Decomposition);
+        }
+        if self.table.is_some() || self.schedule.is_some() {
+            return Err(AnalysisError::Tables);
+        }
+
+        let mut productions = HashMap::new();
+        for (i, rule) in self.production_rules.iter().enumerate() {
+            let token = &rule.token;
+            let mut chars = token.chars();
+            let symbol = match (chars.next(), chars.next()) {
+                (Some(c), None) => Symbol::from(c),
+                _ => return Err(AnalysisError::MultiModuleToken(token.clone())),
+            };
+
+            if rule.left_context.is_some() || rule.right_context.is_some() {
+                return Err(AnalysisError::ContextSensitive(token.clone()));
+            }
+            if rule.successors.len() != 1 {
+                return Err(AnalysisError::Stochastic(token.clone()));
+            }
+            if rule.arity.is_some() || rule.condition.is_some() {
+                return Err(AnalysisError::Parametric(token.clone()));
+            }
+            productions.entry(symbol).or_insert(i);
+        }
+
+        let mut successors = HashMap::new();
+        let mut pending: Vec<_> = self.axiom.iter().map(|m| m.symbol).collect();
+        while let Some(symbol) = pending.pop() {
+            if successors.contains_key(&symbol) {
+                continue;
+            }
+            if Some(symbol) == self.cut {
+                return Err(AnalysisError::Cut);
+            }
+
+            let successor = match productions.get(&symbol) {
+                Some(&i) => {
+                    let rule = &self.production_rules[i];
+                    let word = [Module::from(symbol)];
+                    let matched = Match {
+                        token: &rule.token,
+                        args: &[],
+                        index: 0,
+                        len: 1,
+                        generation: self.generation,
+                        word: &word,
+                    };
+                    (rule.successors[0].1)(&matched)
+                }
+                None => Word::from(vec![Module::from(symbol)]),
+            };
+            pending.extend(successor.iter().map(|m| m.symbol));
+            successors.insert(symbol, successor);
+        }
+
+        Ok(successors)
+    }
+
+    /// Builds the growth matrix of the system, see [`LSystem::successor_map`].
+    pub fn growth_matrix(&self) -> Result<GrowthMatrix, AnalysisError> {
+        let successors = self.successor_map()?;
+        let mut symbols: Vec<_> = successors.keys().copied().collect();
+        symbols.sort();
+        let index: HashMap<_, _> = symbols.iter().enumerate().map(|(i, s)| (*s, i)).collect();
+
+        let count = |word: &Word| {
+            let mut counts = vec![0; symbols.len()];
+            word.iter().for_each(|m| counts[index[&m.symbol]] += 1);
+            counts
+        };
+
+        Ok(GrowthMatrix {
+            matrix: symbols.iter().map(|s| count(&successors[s])).collect(),
+            initial: count(&self.axiom),
+            symbols,
+        })
+    }
+}
+
+/// How the length of a word grows with each generation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Growth {
+    /// Grows like `n^degree`, where a degree of 0 means the length is bounded.
+    Polynomial(usize),
+    Exponential,
+}
+
+/// Symbol occurrence counts of a deterministic context-free system.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GrowthMatrix {
+    pub symbols: Vec<Symbol>,
+    /// `matrix[i][j]` is the number of times `symbols[j]` occurs in the
+    /// successor of `symbols[i]`.
+    pub matrix: Vec<Vec<usize>>,
+    /// The number of times each symbol occurs in the axiom.
+    pub initial: Vec<usize>,
+}
+
+impl GrowthMatrix {
+    /// The number of times each symbol occurs in generation `n`, counting from
+    /// the axiom the matrix was built from.
+    pub fn counts(&self, n: usize) -> Vec<BigUint> {
+        let mut counts: Vec<BigUint> = self.initial.iter().map(|c| BigUint::from(*c)).collect();
+        for _ in 0..n {
+            let mut next = vec![BigUint::default(); counts.len()];
+            for (count, row) in counts.iter().zip(&self.matrix) {
+                for (j, m) in row.iter().enumerate().filter(|(_, m)| **m != 0) {
+                    next[j] += count * *m;
+                }
+            }
+            counts = next;
+        }
+        counts
+    }
+
+    pub fn symbol_counts(&self, n: usize) -> HashMap<Symbol, BigUint> {
+        self.symbols.iter().copied().zip(self.counts(n)).collect()
+    }
+
+    /// The number of modules in generation `n`.
+    pub fn length(&self, n: usize) -> BigUint {
+        self.counts(n).into_iter().sum()
+    }
+
+    /// Classifies growth from the strongly connected components of the symbol
+    /// graph. A component that is more than a single cycle of single
+    /// occurrences grows exponentially, otherwise the degree is one less than
+    /// the most cyclic components along any path.
+    pub fn growth(&self) -> Growth {
+        let components = self.components();
+        let count = components.iter().max().map_or(0, |c| c + 1);
+        let mut members = vec![vec![]; count];
+        components
+            .iter()
+            .enumerate()
+            .for_each(|(i, c)| members[*c].push(i));
+
+        // Tarjan numbers components in reverse topological order, so every
+        // component's successors are visited before it.
+        let mut chain = vec![0; count];
+        for (c, nodes) in members.iter().enumerate() {
+            let internal = |i: usize| -> usize { nodes.iter().map(|j| self.matrix[i][*j]).sum() };
+            if nodes.iter().any(|i| internal(*i) > 1) {
+                return Growth::Exponential;
+            }
+
+            let cyclic = usize::from(nodes.iter().any(|i| internal(*i) == 1));
+            let next = nodes
+                .iter()
+                .flat_map(|i| self.matrix[*i].iter().enumerate())
+                .filter(|(j, m)| **m != 0 && components[*j] != c)
+                .map(|(j, _)| chain[components[j]])
+                .max()
+                .unwrap_or(0);
+            chain[c] = cyclic + next;
+        }
+
+        Growth::Polynomial(chain.into_iter().max().unwrap_or(0).saturating_sub(1))
+    }
+
+    // Tarjan's algorithm, returning the component of each symbol.
+    fn components(&self) -> Vec<usize> {
+        struct State<'a> {
+            matrix: &'a [Vec<usize>],
+            index: Vec<Option<usize>>,
+            low: Vec<usize>,
+            stack: Vec<usize>,
+            on_stack: Vec<bool>,
+            component: Vec<usize>,
+            next_index: usize,
+            next_component: usize,
+        }
+
+        fn visit(s: &mut State, v: usize) {
+            s.index[v] = Some(s.next_index);
+            s.low[v] = s.next_index;
+            s.next_index += 1;
+            s.stack.push(v);
+            s.on_stack[v] = true;
+
+            for w in 0..s.matrix.len() {
+                if s.matrix[v][w] == 0 {
+                    continue;
+                }
+                match s.index[w] {
+                    None => {
+                        visit(s, w);
+                        s.low[v] = s.low[v].min(s.low[w]);
+                    }
+                    Some(i) if s.on_stack[w] => s.low[v] = s.low[v].min(i),
+                    Some(_) => {}
+                }
+            }
+
+            if Some(s.low[v]) == s.index[v] {
+                while let Some(w) = s.stack.pop() {
+                    s.on_stack[w] = false;
+                    s.component[w] = s.next_component;
+                    if w == v {
+                        break;
+                    }
+                }
+                s.next_component += 1;
+            }
+        }
+
+        let n = self.symbols.len();
+        let mut state = State {
+            matrix: &self.matrix,
+            index: vec![None; n],
+            low: vec![0; n],
+            stack: vec![],
+            on_stack: vec![false; n],
+            component: vec![0; n],
+            next_index: 0,
+            next_component: 0,
+        };
+        for v in 0..n {
+            if state.index[v].is_none() {
+                visit(&mut state, v);
+            }
+        }
+        state.component
+    }
+}
@@ -0,0 +1,153 @@
+use crate::word::{Module, Symbol, Word, POP, PUSH};
+use crate::{table_rules, Candidates, LSystem};
+
+/// Yields the modules of a later generation depth first, straight from the
+/// productions, without building the generations in between. Only the
+/// successors along the path to the current module are kept, so memory grows
+/// with the number of generations rather than with the length of the word.
+///
+/// Each module is rewritten within the successor that produced it, so tokens
+/// and contexts never reach into a neighbouring successor, and stochastic
+/// choices are drawn in a different order than [`LSystem::step`] draws them.
+/// For context-free productions over single modules the modules are exactly
+/// those of the word `step_by(n)` would produce.
+pub struct Expansion<'a> {
+    system: &'a mut LSystem,
+    /// The table and its candidates for each level, see [`LSystem::table_for`].
+    levels: Vec<(Option<String>, Candidates)>,
+    /// For each level, whether it is dropping the rest of a branch after the
+    /// cut symbol, see `is_cut`.
+    cuts: Vec<Option<usize>>,
+    depth: usize,
+    position: usize,
+    stack: Vec<(Word, usize)>,
+}
+
+impl<'a> Expansion<'a> {
+    pub(crate) fn new(system: &'a mut LSystem, depth: usize) -> Self {
+        let levels = (0..depth)
+            .map(|level| {
+                let table = system.table_for(system.generation + level);
+                let candidates = Candidates::new(system.rules_mut(table.as_deref()));
+                (table, candidates)
+            })
+            .collect();
+
+        Self {
+            levels,
+            system,
+            cuts: vec![None; depth],
+            depth,
+            position: 0,
+            stack: Vec::with_capacity(depth),
+        }
+    }
+}
+
+impl Iterator for Expansion<'_> {
+    type Item = Module;
+
+    fn next(&mut self) -> Option<Module> {
+        let LSystem {
+            axiom,
+            production_rules,
+            tables,
+            ignore,
+            cut,
+            rng,
+            generation,
+            ..
+        } = &mut *self.system;
+
+        loop {
+            let level = self.stack.len();
+            let (word, position) = match self.stack.last_mut() {
+                Some((word, position)) => (&word[..], position),
+                None => (&axiom[..], &mut self.position),
+            };
+
+            let i = *position;
+            if i == word.len() {
+                self.stack.pop()?;
+                continue;
+            }
+            if level == self.depth {
+                *position += 1;
+                return Some(word[i].clone());
+            }
+            if is_cut(&mut self.cuts[level], *cut, word[i].symbol) {
+                *position += 1;
+                continue;
+            }
+
+            let (table, candidates) = &self.levels[level];
+            let rewritten = candidates.rewrite(
+                table_rules(production_rules, tables, table.as_deref()),
+                word,
+                i,
+                ignore,
+                *generation + level,
+                rng,
+            );
+            if let Some((successor, len)) = rewritten {
+                *position += len;
+                self.stack.push((successor, 0));
+                continue;
+            }
+
+            // A module no production matches is its own successor, which is
+            // carried through the later levels without building a word for it.
+            *position += 1;
+            let module = word[i].clone();
+            for next in level + 1..=self.depth {
+                if next == self.depth {
+                    return Some(module);
+                }
+                if is_cut(&mut self.cuts[next], *cut, module.symbol) {
+                    break;
+                }
+                let (table, candidates) = &self.levels[next];
+                let rewritten = candidates.rewrite(
+                    table_rules(production_rules, tables, table.as_deref()),
+                    std::slice::from_ref(&module),
+                    0,
+                    ignore,
+                    *generation + next,
+                    rng,
+                );
+                if let Some((successor, _)) = rewritten {
+                    // The levels in between have nothing left to rewrite.
+                    self.stack.extend((level..next).map(|_| (Word::new(), 0)));
+                    self.stack.push((successor, 0));
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// Whether a module at some level is dropped by a cut, which like in
+// `LSystem::step` drops the rest of its branch up to the `]` that closes it.
+// `cutting` holds how many branches deep into the dropped part the level is.
+fn is_cut(cutting: &mut Option<usize>, cut: Option<Symbol>, symbol: Symbol) -> bool {
+    match cutting {
+        None if Some(symbol) == cut => {
+            *cutting = Some(0);
+            true
+        }
+        None => false,
+        Some(depth) if symbol == PUSH => {
+            *depth += 1;
+            true
+        }
+        Some(0) if symbol == POP => {
+            *cutting = None;
+            false
+        }
+        Some(depth) if symbol == POP => {
+            *depth -= 1;
+            true
+        }
+        Some(_) => true,
+    }
+}
@@ -0,0 +1,297 @@
+//! Imports the collections of systems in Fractint's `.l` files.
+//!
+//! ```text
+//! Koch { ; a comment
+//!   Angle 6
+//!   Axiom F--F--F
+//!   F=F+F--F+F
+//! }
+//! ```
+//!
+//! Symbols are case insensitive and read as uppercase. `F`, `D`, `G` and `M`
+//! move forward, `+` and `-` turn by the angle, `|` turns around, `!` swaps
+//! the directions of turns, `@` scales the line length, `\` and `/` turn by a
+//! number of degrees and `[` and `]` push and pop the turtle along with the
+//! length and turning direction. A [`BasicTurtle`] can't lift its pen, so `G`
+//! and `M` draw like `F`. Colour commands are read but not executed.
+
+use crate::default_execution_rules::BasicTurtleRegister;
+use crate::expr::ParseError;
+use crate::lsys::GrammarError;
+use crate::turtle::BasicTurtle;
+use crate::word::{number, Module, Symbol, Word};
+use crate::{LSystem, LSystemExecutor, Production};
+use serde::de::DeserializeOwned;
+use std::f64::consts::PI;
+use std::fmt;
+
+/// A system of a `.l` file.
+pub struct FractintSystem {
+    pub name: String,
+    /// The angle `+` and `-` turn by, in radians.
+    pub angle: f64,
+    pub system: LSystem,
+}
+
+impl FractintSystem {
+    /// An executor drawing the system with `turtle`.
+    pub fn executor<T, P>(&self, turtle: T) -> LSystemExecutor<FractintTurtle<T>>
+    where
+        T: 'static + BasicTurtle<f64, f64, P>,
+        T::Error: fmt::Debug,
+        P: 'static + DeserializeOwned,
+    {
+        let mut executor = LSystemExecutor::new(FractintTurtle::new(turtle, self.angle));
+        BasicTurtleRegister::new()
+            .push("[".into())
+            .pop("]".into())
+            .register_parametric::<_, f64, f64, P, _, _>(&mut executor, |s| s);
+
+        for token in &["F", "D", "G", "M"] {
+            executor.register_rule(token.to_string(), |s: &mut FractintTurtle<T>| s.travel(1.));
+        }
+        executor.register_rule("+".into(), |s: &mut FractintTurtle<T>| {
+            let angle = s.angle;
+            s.rotate(angle)
+        });
+        executor.register_rule("-".into(), |s: &mut FractintTurtle<T>| {
+            let angle = s.angle;
+            s.rotate(-angle)
+        });
+        executor.register_rule("|".into(), |s: &mut FractintTurtle<T>| s.rotate(PI));
+        executor.register_rule("!".into(), |s: &mut FractintTurtle<T>| {
+            s.reversed = !s.reversed
+        });
+        executor.register_rule("@".into(), |s: &mut FractintTurtle<T>, f: f64| {
+            s.length *= f
+        });
+        executor.register_rule("\\".into(), |s: &mut FractintTurtle<T>, d: f64| {
+            s.rotate(d.to_radians())
+        });
+        executor.register_rule("/".into(), |s: &mut FractintTurtle<T>, d: f64| {
+            s.rotate(-d.to_radians())
+        });
+        executor
+    }
+}
+
+/// Wraps a turtle with the state Fractint keeps beside it. Distances are
+/// scaled by the line length and turns are mirrored while `reversed`.
+pub struct FractintTurtle<T> {
+    pub turtle: T,
+    pub angle: f64,
+    pub length: f64,
+    pub reversed: bool,
+    stack: Vec<(f64, bool)>,
+}
+
+impl<T> FractintTurtle<T> {
+    pub fn new(turtle: T, angle: f64) -> Self {
+        Self {
+            turtle,
+            angle,
+            length: 1.,
+            reversed: false,
+            stack: vec![],
+        }
+    }
+}
+
+impl<T: BasicTurtle<f64, f64, P>, P> BasicTurtle<f64, f64, P> for FractintTurtle<T> {
+    type Error = T::Error;
+
+    fn heading(&self) -> &f64 {
+        self.turtle.heading()
+    }
+
+    fn rotate(&mut self, radians: f64) {
+        let radians = if self.reversed { -radians } else { radians };
+        self.turtle.rotate(radians)
+    }
+
+    fn position(&self) -> &P {
+        self.turtle.position()
+    }
+
+    fn travel(&mut self, distance: f64) {
+        self.turtle.travel(distance * self.length)
+    }
+
+    fn push(&mut self) {
+        self.stack.push((self.length, self.reversed));
+        self.turtle.push()
+    }
+
+    fn pop(&mut self) {
+        if let Some((length, reversed)) = self.stack.pop() {
+            self.length = length;
+            self.reversed = reversed;
+        }
+        self.turtle.pop()
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.turtle.flush()
+    }
+}
+
+// A system whose closing brace hasn't been reached yet.
+struct Entry {
+    name: String,
+    angle: f64,
+    axiom: Word,
+    productions: Vec<Production>,
+}
+
+/// Reads every system of a `.l` file.
+pub fn parse(source: &str) -> Result<Vec<FractintSystem>, GrammarError> {
+    let mut systems = vec![];
+    let mut entry: Option<Entry> = None;
+
+    for (i, line) in source.lines().enumerate() {
+        let error = |column: usize, message: &str| GrammarError {
+            message: message.into(),
+            line: i + 1,
+            column: line[..column].chars().count() + 1,
+        };
+        let content = line.split(';').next().unwrap();
+        let start = content.len() - content.trim_start().len();
+        let mut content = content.trim();
+
+        if entry.is_none() {
+            if content.is_empty() {
+                continue;
+            }
+            let brace = content
+                .find('{')
+                .ok_or_else(|| error(start, "expected `{` after the name"))?;
+            entry = Some(Entry {
+                name: content[..brace].trim().to_string(),
+                angle: PI / 2.,
+                axiom: Word::new(),
+                productions: vec![],
+            });
+            content = content[brace + 1..].trim();
+        }
+
+        let closed = content.ends_with('}');
+        let content = content.trim_end_matches('}').trim();
+        if !content.is_empty() {
+            let current = entry.as_mut().unwrap();
+            let offset = offset(line, content);
+            read_line(current, content).map_err(|e| error(offset + e.position, &e.message))?;
+        }
+
+        if closed {
+            let Entry {
+                name,
+                angle,
+                axiom,
+                productions,
+            } = entry.take().unwrap();
+            let mut system = LSystem::new(String::new());
+            system.axiom = axiom;
+            system.production_rules = productions;
+            systems.push(FractintSystem {
+                name,
+                angle,
+                system,
+            });
+        }
+    }
+
+    if let Some(entry) = entry {
+        return Err(GrammarError {
+            message: format!("`{}` is missing its closing `}}`", entry.name),
+            line: source.lines().count(),
+            column: 1,
+        });
+    }
+    Ok(systems)
+}
+
+fn read_line(entry: &mut Entry, line: &str) -> Result<(), ParseError> {
+    let error = |message: &str| ParseError {
+        message: message.into(),
+        position: 0,
+    };
+    let keyword = line.split_whitespace().next().unwrap().to_lowercase();
+    let rest = line[line.find(char::is_whitespace).unwrap_or(line.len())..].trim();
+
+    match keyword.as_str() {
+        "axiom" => {
+            entry.axiom = word(rest).map_err(|e| ParseError {
+                position: e.position + offset(line, rest),
+                ..e
+            })?
+        }
+        "angle" => {
+            let divisions: f64 = rest.parse().map_err(|_| error("expected a number"))?;
+            entry.angle = 2. * PI / divisions;
+        }
+        _ => {
+            let equals = line.find('=').ok_or_else(|| error("expected a rule"))?;
+            let token = line[..equals].trim().to_uppercase();
+            if token.chars().count() != 1 {
+                return Err(error("expected a single symbol before `=`"));
+            }
+            let successor = word(&line[equals + 1..]).map_err(|e| ParseError {
+                position: e.position + equals + 1,
+                ..e
+            })?;
+            let production = Production::from_words(token, vec![(1., successor)]);
+            entry.productions.push(production);
+        }
+    }
+    Ok(())
+}
+
+// The byte offset of `inner` within `outer`, which it must be a slice of.
+fn offset(outer: &str, inner: &str) -> usize {
+    inner.as_ptr() as usize - outer.as_ptr() as usize
+}
+
+// Reads a Fractint string, giving the commands that take a number a module
+// parameter holding it.
+fn word(text: &str) -> Result<Word, ParseError> {
+    let mut modules = vec![];
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        let c = c.to_ascii_uppercase();
+        let mut module = Module::from(Symbol::from(c));
+
+        if "@\\/C<>".contains(c) {
+            let (mut inverse, mut root) = (false, false);
+            while let Some((_, m)) = chars.next_if(|(_, m)| c == '@' && "IiQq".contains(*m)) {
+                if m.eq_ignore_ascii_case(&'i') {
+                    inverse = true;
+                } else {
+                    root = true;
+                }
+            }
+
+            let mut digits = String::new();
+            while let Some((_, d)) = chars.next_if(|(_, d)| d.is_ascii_digit() || *d == '.') {
+                digits.push(d);
+            }
+            let mut value: f64 = digits.parse().map_err(|_| ParseError {
+                message: format!("expected a number after `{}`", c),
+                position: i + 1,
+            })?;
+            if root {
+                value = value.sqrt();
+            }
+            if inverse {
+                value = 1. / value;
+            }
+            module.params.push(number(value));
+        }
+        modules.push(module);
+    }
+
+    Ok(Word(modules))
+}
@@ -0,0 +1,255 @@
+//! Records derivations so that a system can step backward as well as forward,
+//! and the module a symbol came from can be traced back through generations.
+
+use crate::word::Word;
+use crate::LSystem;
+use rand_chacha::ChaCha8Rng;
+use std::mem;
+use std::ops::Range;
+
+/// What a [`History`] keeps of each generation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Recording {
+    /// A copy of every word, so any generation can be read back directly.
+    Words,
+    /// Only the modules each step replaced and inserted, so reading back a
+    /// generation replays the steps leading to it.
+    Diffs,
+}
+
+/// A production, or the cut symbol, applied at one position during a step.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Application {
+    /// The index of the production among the rules of its pass, or `None` for
+    /// a cut.
+    pub production: Option<usize>,
+    /// The modules replaced, as indices into the word the pass read.
+    pub source: Range<usize>,
+    /// The modules of the successor, as indices into the word the pass wrote.
+    pub target: Range<usize>,
+    /// The number in `[0, 1)` drawn from the rng to pick between the
+    /// successors of a stochastic production.
+    pub draw: Option<f64>,
+    /// The modules of `source` and `target`, only kept when recording diffs.
+    pub replaced: Word,
+    pub successor: Word,
+}
+
+/// A recorded step.
+#[derive(Clone, Debug)]
+pub struct Step {
+    /// The table the productions came from.
+    pub table: Option<String>,
+    /// The applications of the production pass followed by those of each
+    /// decomposition pass, in order of position.
+    pub passes: Vec<Vec<Application>>,
+    rng_before: ChaCha8Rng,
+    rng_after: ChaCha8Rng,
+    word: Option<Word>,
+}
+
+impl Step {
+    pub(crate) fn new(
+        table: Option<&str>,
+        passes: Vec<Vec<Application>>,
+        rng_before: ChaCha8Rng,
+        rng_after: ChaCha8Rng,
+    ) -> Self {
+        Self {
+            table: table.map(String::from),
+            passes,
+            rng_before,
+            rng_after,
+            word: None,
+        }
+    }
+
+    // Rewrites the word before the step into the word after it.
+    fn redo(&self, word: &Word) -> Word {
+        self.passes.iter().fold(word.clone(), |word, pass| {
+            splice(&word, pass, |a| (&a.source, &a.successor))
+        })
+    }
+
+    // Rewrites the word after the step back into the word before it.
+    fn undo(&self, word: &Word) -> Word {
+        self.passes.iter().rev().fold(word.clone(), |word, pass| {
+            splice(&word, pass, |a| (&a.target, &a.replaced))
+        })
+    }
+}
+
+// Copies `word`, putting the modules each application gives in place of the
+// range it gives.
+fn splice(
+    word: &Word,
+    pass: &[Application],
+    f: impl Fn(&Application) -> (&Range<usize>, &Word),
+) -> Word {
+    let mut spliced = Word(Vec::with_capacity(word.len()));
+    let mut i = 0;
+    for application in pass {
+        let (range, modules) = f(application);
+        spliced.extend_from_slice(&word[i..range.start]);
+        spliced.extend_from_slice(modules);
+        i = range.end;
+    }
+    spliced.extend_from_slice(&word[i..]);
+    spliced
+}
+
+/// Where a module of a recorded generation came from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Origin<'a> {
+    /// The index of the module in the previous generation that it came from.
+    pub index: usize,
+    /// The applications that produced it, production pass first. It's empty
+    /// if the module was copied over unchanged.
+    pub applications: Vec<&'a Application>,
+}
+
+/// The steps taken since recording started, see [`LSystem::record`].
+#[derive(Clone, Debug)]
+pub struct History {
+    pub recording: Recording,
+    /// The generation recording started at.
+    pub start: usize,
+    /// The word at `start`.
+    pub initial: Word,
+    /// The steps taken from `start` on. Stepping back keeps them so that they
+    /// can be stepped forward through again, until another step is taken.
+    pub steps: Vec<Step>,
+}
+
+impl History {
+    pub fn new(recording: Recording, word: Word, generation: usize) -> Self {
+        Self {
+            recording,
+            start: generation,
+            initial: word,
+            steps: vec![],
+        }
+    }
+
+    // Adds the step from `generation`, dropping any steps recorded after it.
+    pub(crate) fn push(&mut self, generation: usize, before: &Word, after: &Word, mut step: Step) {
+        if generation < self.start || generation - self.start > self.steps.len() {
+            *self = Self::new(self.recording, before.clone(), generation);
+        }
+        self.steps.truncate(generation - self.start);
+
+        if self.recording == Recording::Words {
+            for application in step.passes.iter_mut().flatten() {
+                mem::take(&mut application.replaced);
+                mem::take(&mut application.successor);
+            }
+            step.word = Some(after.clone());
+        }
+        self.steps.push(step);
+    }
+
+    /// The recorded word of a generation.
+    pub fn word(&self, generation: usize) -> Option<Word> {
+        let steps = generation.checked_sub(self.start)?;
+        if steps > self.steps.len() {
+            return None;
+        }
+        Some(match (steps, self.recording) {
+            (0, _) => self.initial.clone(),
+            (_, Recording::Words) => self.steps[steps - 1].word.clone().unwrap(),
+            (_, Recording::Diffs) => self.steps[..steps]
+                .iter()
+                .fold(self.initial.clone(), |word, step| step.redo(&word)),
+        })
+    }
+
+    /// The step leading up to a generation.
+    pub fn step(&self, generation: usize) -> Option<&Step> {
+        let steps = generation.checked_sub(self.start)?;
+        self.steps.get(steps.checked_sub(1)?)
+    }
+
+    /// Traces the module at `index` of a generation back to the previous one.
+    pub fn origin(&self, generation: usize, index: usize) -> Option<Origin<'_>> {
+        let step = self.step(generation)?;
+        let mut index = index;
+        let mut applications = vec![];
+        for pass in step.passes.iter().rev() {
+            let next = pass.partition_point(|a| a.target.end <= index);
+            match pass.get(next) {
+                Some(a) if a.target.contains(&index) => {
+                    index = a.source.start;
+                    applications.push(a);
+                }
+                _ => {
+                    if let Some(a) = next.checked_sub(1).map(|p| &pass[p]) {
+                        index = a.source.end + (index - a.target.end);
+                    }
+                }
+            }
+        }
+        applications.reverse();
+        Some(Origin {
+            index,
+            applications,
+        })
+    }
+
+    /// Traces the module at `index` of a generation back to the module of the
+    /// word recording started with that it descends from.
+    pub fn ancestor(&self, generation: usize, index: usize) -> Option<usize> {
+        (self.start + 1..=generation)
+            .rev()
+            .try_fold(index, |index, generation| {
+                Some(self.origin(generation, index)?.index)
+            })
+    }
+}
+
+impl LSystem {
+    /// Starts recording every step, from the current generation on.
+    pub fn record(&mut self, recording: Recording) {
+        self.history = Some(History::new(recording, self.axiom.clone(), self.generation));
+    }
+
+    /// Goes back to the previous recorded generation, restoring its word and
+    /// the rng, and returns whether there was one. Timed rewriting by
+    /// [`LSystem::advance`] isn't recorded.
+    pub fn step_back(&mut self) -> bool {
+        let history = match &self.history {
+            Some(history) => history,
+            None => return false,
+        };
+        let step = match history.step(self.generation) {
+            Some(step) => step,
+            None => return false,
+        };
+        self.axiom = match history.recording {
+            Recording::Words => history.word(self.generation - 1).unwrap(),
+            Recording::Diffs => step.undo(&self.axiom),
+        };
+        self.rng = step.rng_before.clone();
+        self.generation -= 1;
+        true
+    }
+
+    /// Replays the recorded step after the current generation, if it was
+    /// stepped back from, and returns whether there was one.
+    pub fn step_forward(&mut self) -> bool {
+        let history = match &self.history {
+            Some(history) => history,
+            None => return false,
+        };
+        let step = match history.step(self.generation + 1) {
+            Some(step) => step,
+            None => return false,
+        };
+        self.axiom = match &step.word {
+            Some(word) => word.clone(),
+            None => step.redo(&self.axiom),
+        };
+        self.rng = step.rng_after.clone();
+        self.generation += 1;
+        true
+    }
+}
@@ -0,0 +1,155 @@
+//! Recovers deterministic context-free systems from the words of consecutive
+//! generations.
+
+use crate::word::{Module, Symbol, Word};
+use crate::{LSystem, Production};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// Limits on the systems [`infer`] searches through.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Bounds {
+    /// The longest successor considered.
+    pub max_successor_len: usize,
+    /// The most distinct symbols the generations may use.
+    pub max_alphabet: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InferenceError {
+    /// Fewer than two generations were given, which any system reproduces.
+    TooFewGenerations,
+    /// The generations use this many symbols, more than the bounds allow.
+    Alphabet(usize),
+}
+
+impl fmt::Display for InferenceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::TooFewGenerations => f.write_str("at least two generations are needed"),
+            Self::Alphabet(n) => write!(f, "the generations use {} symbols", n),
+        }
+    }
+}
+
+impl std::error::Error for InferenceError {}
+
+/// A system found by [`infer`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct D0LSystem {
+    pub axiom: Word,
+    /// The successor of every symbol in the generations before the last, which
+    /// may be the symbol itself. Symbols only in the last generation are left
+    /// out, since any successor would do for them.
+    pub successors: BTreeMap<Symbol, Word>,
+}
+
+impl D0LSystem {
+    /// Builds the system, with a production for every symbol that isn't its
+    /// own successor.
+    pub fn system(&self) -> LSystem {
+        let mut system = LSystem::new(String::new());
+        system.axiom = self.axiom.clone();
+        for (symbol, successor) in &self.successors {
+            if successor.len() == 1 && successor[0].symbol == *symbol {
+                continue;
+            }
+            system.production_rules.push(Production::from_words(
+                symbol.name(),
+                vec![(1., successor.clone())],
+            ));
+        }
+        system
+    }
+}
+
+/// Finds every system within the bounds whose axiom is the first generation and
+/// whose steps produce the others in order. Modules are compared by symbol, so
+/// their parameters are ignored.
+pub fn infer(generations: &[Word], bounds: Bounds) -> Result<Vec<D0LSystem>, InferenceError> {
+    if generations.len() < 2 {
+        return Err(InferenceError::TooFewGenerations);
+    }
+    let words: Vec<Vec<Symbol>> = generations
+        .iter()
+        .map(|word| word.iter().map(|m| m.symbol).collect())
+        .collect();
+    let alphabet: BTreeSet<Symbol> = words.iter().flatten().copied().collect();
+    if alphabet.len() > bounds.max_alphabet {
+        return Err(InferenceError::Alphabet(alphabet.len()));
+    }
+
+    let mut search = Search {
+        words: &words,
+        max_len: bounds.max_successor_len,
+        successors: BTreeMap::new(),
+        found: vec![],
+    };
+    search.solve(0, 0, 0);
+
+    Ok(search
+        .found
+        .into_iter()
+        .map(|successors| D0LSystem {
+            axiom: generations[0].clone(),
+            successors: successors
+                .into_iter()
+                .map(|(symbol, successor)| {
+                    let successor = successor.into_iter().map(Module::from).collect();
+                    (symbol, successor)
+                })
+                .collect(),
+        })
+        .collect())
+}
+
+// Backtracks over the successors of symbols in the order they first occur.
+struct Search<'a> {
+    words: &'a [Vec<Symbol>],
+    max_len: usize,
+    successors: BTreeMap<Symbol, Vec<Symbol>>,
+    found: Vec<BTreeMap<Symbol, Vec<Symbol>>>,
+}
+
+impl<'a> Search<'a> {
+    // Continues from module `i` of generation `p`, whose successors so far make
+    // up the first `j` modules of the next generation.
+    fn solve(&mut self, mut p: usize, mut i: usize, mut j: usize) {
+        let words = self.words;
+        // Symbols with a successor already are checked without recursing.
+        loop {
+            if p + 1 == words.len() {
+                self.found.push(self.successors.clone());
+                return;
+            }
+            let (from, to) = (&words[p], &words[p + 1]);
+            if i == from.len() {
+                if j != to.len() {
+                    return;
+                }
+                p += 1;
+                i = 0;
+                j = 0;
+                continue;
+            }
+            if to.len() - j > (from.len() - i) * self.max_len {
+                return;
+            }
+            match self.successors.get(&from[i]) {
+                Some(successor) if to[j..].starts_with(successor) => {
+                    j += successor.len();
+                    i += 1;
+                }
+                Some(_) => return,
+                None => break,
+            }
+        }
+
+        let (symbol, to) = (words[p][i], &words[p + 1]);
+        for len in 0..=self.max_len.min(to.len() - j) {
+            self.successors.insert(symbol, to[j..j + len].to_vec());
+            self.solve(p, i + 1, j + len);
+        }
+        self.successors.remove(&symbol);
+    }
+}
@@ -1,17 +1,92 @@
+// Lets `lsystem!` name the crate as `::l_system` from within it too.
+extern crate self as l_system;
+
+pub mod access;
+pub mod analysis;
+pub mod default_execution_rules;
+pub mod expansion;
+pub mod fractint;
+pub mod history;
+pub mod inference;
+pub mod limits;
+pub mod lint;
+pub mod lpy;
+pub mod lsys;
+#[doc(hidden)]
+pub mod macros;
+#[cfg(feature = "rayon")]
+pub mod parallel;
+pub mod parametric;
+pub mod spec;
+pub mod stability;
 #[cfg(test)]
 mod tests;
+pub mod timed;
 pub mod turtle;
-pub mod default_execution_rules;
+pub mod word;
 
+pub use access::RandomAccess;
+use analysis::AnalysisError;
+pub use expansion::Expansion;
+use expr::ParseError;
+use history::{Application, History, Step};
+/// Defines a system in textbook notation.
+///
+/// ```
+/// let mut system = l_system::lsystem! {
+///     const LEN = 10;
+///     axiom: F(LEN) X;
+///     X -> F(LEN) [+X] [-X] F(LEN) X : 0.7;
+///     X -> F(LEN) [-X] F(LEN) X : 0.3;
+///     F(x) : x > 1 -> F(x * 0.9);
+/// };
+/// system.step();
+/// ```
+///
+/// Statements end in `;` and are constants, written `const NAME = expr`, the
+/// axiom, or productions in the syntax of the [`lsys`] format, with an optional
+/// guard after the predecessor and an optional weight after the successor.
+/// Whitespace within templates is ignored. The grammar is checked as the macro
+/// expands, by the same parser the format uses, so a mistake such as an
+/// unknown variable or weights that are all zero fails to compile with an
+/// error pointing at it:
+///
+/// ```compile_fail
+/// let system = l_system::lsystem! {
+///     axiom: A;
+///     A(x) : y > 1 -> B;
+/// };
+/// ```
+///
+/// ```compile_fail
+/// let system = l_system::lsystem! {
+///     axiom: A;
+///     A -> B : 0;
+///     A -> C : 0;
+/// };
+/// ```
+///
+/// Symbols that aren't Rust tokens, such as `\`, can't be used.
+pub use l_system_macros::lsystem;
+pub use l_system_syntax::expr;
+pub(crate) use l_system_syntax::rule::check_weights;
+pub use l_system_syntax::rule::WeightError;
+use limits::{Guard, LimitError, Limits};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::{hash_map::RandomState, HashMap, VecDeque};
+use std::hash::{BuildHasher, Hasher};
 use std::marker::PhantomData;
+use word::{match_token, Module, Symbol, Word, POP, PUSH};
 
 pub trait CallParsed<State, T> {
-    fn call_parsed(&mut self, state: &mut State, args: String) -> Result<(), serde_json::Error>;
+    fn call_parsed(&mut self, state: &mut State, args: &[Value]) -> Result<(), serde_json::Error>;
 }
 
 trait CallParsedErased<State> {
-    fn call_parsed(&mut self, state: &mut State, args: String) -> Result<(), serde_json::Error>;
+    fn call_parsed(&mut self, state: &mut State, args: &[Value]) -> Result<(), serde_json::Error>;
 }
 
 struct Wrapper<State, T, C> {
@@ -20,7 +95,7 @@ struct Wrapper<State, T, C> {
 }
 
 impl<State, T, C: CallParsed<State, T>> CallParsedErased<State> for Wrapper<State, T, C> {
-    fn call_parsed(&mut self, state: &mut State, args: String) -> Result<(), serde_json::Error> {
+    fn call_parsed(&mut self, state: &mut State, args: &[Value]) -> Result<(), serde_json::Error> {
         self.data.call_parsed(state, args)
     }
 }
@@ -34,13 +109,21 @@ macro_rules! call_parsed_impls {
             $( $tail: DeserializeOwned ),*
 
         {
-            fn call_parsed(&mut self, state8348912731: &mut EUCBNAJHXIZAD81923IX, mut args: String) -> Result<(), serde_json::Error> {
-                args = args.chars().skip(1).take(args.len() - 1).collect();
-                args.insert(0, '[');
-                args.push(']');
+            fn call_parsed(&mut self, state8348912731: &mut EUCBNAJHXIZAD81923IX, args: &[Value]) -> Result<(), serde_json::Error> {
+                let arity = [stringify!($head), $( stringify!($tail) ),*].len();
+                if args.len() != arity {
+                    return Err(serde::de::Error::invalid_length(
+                        args.len(),
+                        &format!("{} parameters", arity).as_str(),
+                    ));
+                }
 
+                let mut args = args.iter();
                 #[allow(non_snake_case)]
-                let ($head, $( $tail ),*): ($head, $( $tail ),*) = serde_json::from_str(&args)?;
+                let ($head, $( $tail ),*): ($head, $( $tail ),*) = (
+                    <$head as serde::Deserialize>::deserialize(args.next().unwrap())?,
+                    $( <$tail as serde::Deserialize>::deserialize(args.next().unwrap())? ),*
+                );
 
                 (self)(state8348912731, $head, $( $tail ),*);
                 Ok(())
@@ -54,7 +137,7 @@ macro_rules! call_parsed_impls {
             where
                 F: FnMut(&mut State)
         {
-            fn call_parsed(&mut self, state: &mut State, _: String) -> Result<(), serde_json::Error> {
+            fn call_parsed(&mut self, state: &mut State, _: &[Value]) -> Result<(), serde_json::Error> {
                 (self)(state);
                 Ok(())
             }
@@ -64,9 +147,334 @@ macro_rules! call_parsed_impls {
 
 call_parsed_impls!(A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P,);
 
+/// Produces a replacement for the modules a production matched. Successors
+/// were `FnMut` closures before the `rayon` feature. They are now `Fn`, and
+/// always `Send` and `Sync`, so that `par_step` can share the productions
+/// between threads and enabling the feature never breaks a build. A successor
+/// that keeps state does so in atomics or a `Mutex`.
+pub type Successor = Box<dyn Fn(&Match) -> Word + Send + Sync>;
+pub type Condition = Box<dyn Fn(&[f64]) -> bool + Send + Sync>;
+/// Names the production table to use for a generation, `None` meaning
+/// [`LSystem::production_rules`].
+pub type Schedule = Box<dyn Fn(usize) -> Option<String> + Send + Sync>;
+
+pub struct Production {
+    pub token: String,
+    /// The number of numeric parameters the token must carry, or `None` to
+    /// match the token whatever parameters it has.
+    pub arity: Option<usize>,
+    pub condition: Option<Condition>,
+    pub left_context: Option<Word>,
+    pub right_context: Option<Word>,
+    /// Weighted alternatives for the replacement. A single successor is applied
+    /// without drawing from the rng, whatever its weight.
+    pub successors: Vec<(f64, Successor)>,
+    /// The symbols each successor produces, when they're known without calling
+    /// it, as for stochastic and template productions.
+    pub successor_symbols: Option<Vec<Vec<Symbol>>>,
+    /// Whether the successors read the [`Match`] they're given. [`lint`] calls
+    /// those that don't, like the ones [`Production::new`] makes, to learn
+    /// what they produce.
+    pub reads_match: bool,
+}
+
+impl Production {
+    pub fn new(token: String, replacement: impl 'static + Send + Sync + Fn() -> String) -> Self {
+        let mut production = Self::with_successors(
+            token,
+            vec![(1., Box::new(move |_: &Match| replacement().into()))],
+        );
+        production.reads_match = false;
+        production
+    }
+
+    pub fn from_match(
+        token: String,
+        replacement: impl 'static + Send + Sync + Fn(&Match) -> String,
+    ) -> Self {
+        Self::with_successors(
+            token,
+            vec![(1., Box::new(move |m: &Match| replacement(m).into()))],
+        )
+    }
+
+    pub fn with_successors(token: String, successors: Vec<(f64, Successor)>) -> Self {
+        Self {
+            token,
+            arity: None,
+            condition: None,
+            left_context: None,
+            right_context: None,
+            successors,
+            successor_symbols: None,
+            reads_match: true,
+        }
+    }
+
+    /// A production picking one of `successors` with probability proportional
+    /// to its weight, failing unless the weights are numbers of at least zero
+    /// and not all zero.
+    pub fn stochastic(token: String, successors: Vec<(f64, String)>) -> Result<Self, WeightError> {
+        check_weights(successors.iter().map(|s| s.0))?;
+        Ok(Self::from_words(
+            token,
+            successors
+                .into_iter()
+                .map(|(weight, successor)| (weight, Word::from(successor)))
+                .collect(),
+        ))
+    }
+
+    /// A production whose successors are fixed words.
+    pub fn from_words(token: String, successors: Vec<(f64, Word)>) -> Self {
+        let symbols = successors
+            .iter()
+            .map(|(_, word)| word.iter().map(|m| m.symbol).collect())
+            .collect();
+        let mut production = Self::with_successors(
+            token,
+            successors
+                .into_iter()
+                .map(|(weight, successor)| {
+                    (
+                        weight,
+                        Box::new(move |_: &Match| successor.clone()) as Successor,
+                    )
+                })
+                .collect(),
+        );
+        production.successor_symbols = Some(symbols);
+        production.reads_match = false;
+        production
+    }
+
+    pub fn with_context(
+        left_context: Option<String>,
+        token: String,
+        right_context: Option<String>,
+        replacement: impl 'static + Send + Sync + Fn() -> String,
+    ) -> Self {
+        Self {
+            left_context: left_context.map(Word::from),
+            right_context: right_context.map(Word::from),
+            ..Self::new(token, replacement)
+        }
+    }
+
+    /// Matches the token and its parameters at the start of `modules`,
+    /// returning the parameters and the number of modules matched.
+    fn match_parameters(&self, modules: &[Module]) -> Option<(Vec<f64>, usize)> {
+        let len = match_token(modules, &self.token)?;
+        let last = &modules[len - 1];
+        let args = match self.arity {
+            None => vec![],
+            Some(n) => last.numeric_params().filter(|args| args.len() == n)?,
+        };
+
+        if let Some(condition) = &self.condition {
+            if !condition(&args) {
+                return None;
+            }
+        }
+        Some((args, len))
+    }
+
+    fn context_matches(&self, word: &[Module], start: usize, end: usize, ignore: &Word) -> bool {
+        self.left_context
+            .as_ref()
+            .is_none_or(|c| left_context_matches(&word[..start], c, ignore))
+            && self
+                .right_context
+                .as_ref()
+                .is_none_or(|c| right_context_matches(&word[end..], c, ignore))
+    }
+}
+
+// Picks a successor and applies it, along with the number drawn to pick it when
+// there was a choice.
+fn replace(
+    successors: &[(f64, Successor)],
+    matched: &Match,
+    rng: &mut ChaCha8Rng,
+) -> (Word, Option<f64>) {
+    if let [(_, successor)] = successors {
+        return (successor(matched), None);
+    }
+
+    let draw = rng.gen::<f64>();
+    let total: f64 = successors.iter().map(|s| s.0).sum();
+    let mut choice = draw * total;
+    for (weight, successor) in successors {
+        if choice < *weight {
+            return (successor(matched), Some(draw));
+        }
+        choice -= *weight;
+    }
+
+    let successor = successors
+        .iter()
+        .rev()
+        .find(|s| s.0 > 0.)
+        .map_or_else(Word::new, |s| (s.1)(matched));
+    (successor, Some(draw))
+}
+
+/// Where and when a production matched, handed to its successor.
+pub struct Match<'a> {
+    pub token: &'a str,
+    /// The numeric parameters bound by the production's arity.
+    pub args: &'a [f64],
+    /// The index of the first matched module in the word.
+    pub index: usize,
+    /// The number of modules matched.
+    pub len: usize,
+    /// The generation of the word being rewritten, starting at 0 for the axiom.
+    pub generation: usize,
+    pub word: &'a [Module],
+}
+
+impl<'a> Match<'a> {
+    pub fn modules(&self) -> &'a [Module] {
+        &self.word[self.index..self.index + self.len]
+    }
+
+    /// The parameters of the last matched module.
+    pub fn params(&self) -> &'a [Value] {
+        &self.word[self.index + self.len - 1].params
+    }
+
+    pub fn left(&self) -> &'a [Module] {
+        &self.word[..self.index]
+    }
+
+    pub fn right(&self) -> &'a [Module] {
+        &self.word[self.index + self.len..]
+    }
+
+    /// The module `offset` places away from the match, so `-1` is the module
+    /// just before it and `1` the module just after it.
+    pub fn neighbour(&self, offset: isize) -> Option<&'a Module> {
+        match offset {
+            0 => None,
+            o if o < 0 => self.left().iter().rev().nth(o.unsigned_abs() - 1),
+            o => self.right().get(o as usize - 1),
+        }
+    }
+}
+
+fn is_ignored(module: &Module, ignore: &Word) -> bool {
+    ignore.iter().any(|m| m.symbol == module.symbol)
+}
+
+// Walks left from the predecessor, skipping ignored symbols and whole branches
+// and stepping out of the enclosing branch to reach the parent symbol.
+fn left_context_matches(before: &[Module], context: &Word, ignore: &Word) -> bool {
+    let mut modules = before.iter().rev();
+
+    'context: for expected in context.iter().rev() {
+        while let Some(module) = modules.next() {
+            if module.symbol == POP {
+                let mut depth = 1;
+                while depth != 0 {
+                    match modules.next().map(|m| m.symbol) {
+                        Some(PUSH) => depth -= 1,
+                        Some(POP) => depth += 1,
+                        Some(_) => {}
+                        None => return false,
+                    }
+                }
+            } else if module.symbol == expected.symbol {
+                continue 'context;
+            } else if module.symbol != PUSH && !is_ignored(module, ignore) {
+                return false;
+            }
+        }
+        return false;
+    }
+
+    true
+}
+
+// Walks right from the predecessor. Branches in the word are skipped unless the
+// context asks for them with `[`, and a `]` in the context skips to the end of
+// the current branch.
+fn right_context_matches(after: &[Module], context: &Word, ignore: &Word) -> bool {
+    let mut modules = after.iter();
+
+    fn skip_branch<'a>(modules: &mut impl Iterator<Item = &'a Module>) -> bool {
+        let mut depth = 1;
+        while depth != 0 {
+            match modules.next().map(|m| m.symbol) {
+                Some(PUSH) => depth += 1,
+                Some(POP) => depth -= 1,
+                Some(_) => {}
+                None => return false,
+            }
+        }
+        true
+    }
+
+    'context: for expected in context.iter() {
+        if expected.symbol == POP {
+            if !skip_branch(&mut modules) {
+                return false;
+            }
+            continue;
+        }
+
+        while let Some(module) = modules.next() {
+            if module.symbol == expected.symbol {
+                continue 'context;
+            } else if module.symbol == PUSH {
+                if !skip_branch(&mut modules) {
+                    return false;
+                }
+            } else if module.symbol == POP || !is_ignored(module, ignore) {
+                return false;
+            }
+        }
+        return false;
+    }
+
+    true
+}
+
 pub struct LSystem {
-    pub axiom: String,
-    pub production_rules: Vec<(String, Box<dyn FnMut() -> String>)>,
+    pub axiom: Word,
+    pub production_rules: Vec<Production>,
+    /// Named production tables that can stand in for `production_rules`.
+    pub tables: HashMap<String, Vec<Production>>,
+    /// The table [`LSystem::step`] rewrites with, unless there is a schedule.
+    pub table: Option<String>,
+    /// Picks the table for each generation, taking precedence over `table`.
+    pub schedule: Option<Schedule>,
+    /// Productions applied to the successors within the same step, up to
+    /// `decomposition_depth` times.
+    pub decomposition_rules: Vec<Production>,
+    pub decomposition_depth: usize,
+    /// Productions applied only when the word is executed, up to
+    /// `interpretation_depth` times, leaving the stored word as it is.
+    pub interpretation_rules: Vec<Production>,
+    pub interpretation_depth: usize,
+    /// The symbol that, during a step, removes itself and the rest of its
+    /// branch up to the closing bracket, or the rest of the word outside any
+    /// branch. Defaults to `%`.
+    pub cut: Option<Symbol>,
+    /// Symbols that are skipped over when matching the context of a production.
+    pub ignore: Word,
+    /// Picks between the successors of stochastic productions.
+    pub rng: ChaCha8Rng,
+    /// The number of steps taken since the axiom.
+    pub generation: usize,
+    /// The age at which modules of a symbol mature and get rewritten by
+    /// [`LSystem::advance`]. Modules of other symbols only grow older.
+    pub terminal_ages: HashMap<Symbol, f64>,
+    /// The time [`LSystem::advance`] has moved the system forward by.
+    pub time: f64,
+    /// Records the steps taken once set, see [`LSystem::record`].
+    pub history: Option<History>,
+    /// Bounds the words of [`LSystem::try_step`], which `step` ignores.
+    pub limits: Limits,
 }
 
 impl LSystem {
@@ -74,62 +482,470 @@ impl LSystem {
         Self::with_rules(axiom, vec![])
     }
 
-    pub fn with_rules(
-        axiom: String,
-        production_rules: Vec<(String, Box<dyn FnMut() -> String>)>,
-    ) -> Self {
+    pub fn with_rules(axiom: String, production_rules: Vec<Production>) -> Self {
         Self {
-            axiom,
+            axiom: axiom.into(),
             production_rules,
+            tables: HashMap::new(),
+            table: None,
+            schedule: None,
+            decomposition_rules: vec![],
+            decomposition_depth: 1,
+            interpretation_rules: vec![],
+            interpretation_depth: 1,
+            cut: Some(Symbol::from('%')),
+            ignore: Word::new(),
+            rng: ChaCha8Rng::seed_from_u64(RandomState::new().build_hasher().finish()),
+            generation: 0,
+            terminal_ages: HashMap::new(),
+            time: 0.,
+            history: None,
+            limits: Limits::default(),
         }
     }
 
+    /// Reseeds the rng so that stochastic derivations can be reproduced.
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = ChaCha8Rng::seed_from_u64(seed);
+    }
+
     pub fn register_rule(
         &mut self,
         token: String,
-        replacement: impl 'static + FnMut() -> String,
+        replacement: impl 'static + Send + Sync + Fn() -> String,
+    ) {
+        self.production_rules
+            .push(Production::new(token, replacement));
+    }
+
+    /// Registers a context sensitive rule, written `left < token > right -> replacement`
+    /// in textbook notation.
+    pub fn register_context_rule(
+        &mut self,
+        left_context: Option<String>,
+        token: String,
+        right_context: Option<String>,
+        replacement: impl 'static + Send + Sync + Fn() -> String,
     ) {
-        self.production_rules.push((token, Box::new(replacement)));
+        self.production_rules.push(Production::with_context(
+            left_context,
+            token,
+            right_context,
+            replacement,
+        ));
     }
 
+    /// Registers a rule whose replacement is computed from the [`Match`] it
+    /// replaces, so it can depend on the matched parameters, its neighbours and
+    /// the generation.
+    pub fn register_match_rule(
+        &mut self,
+        token: String,
+        replacement: impl 'static + Send + Sync + Fn(&Match) -> String,
+    ) {
+        self.production_rules
+            .push(Production::from_match(token, replacement));
+    }
+
+    /// Registers a rule that picks one of several successors at random, with
+    /// probability proportional to its weight, see [`Production::stochastic`].
+    pub fn register_stochastic_rule(
+        &mut self,
+        token: String,
+        successors: Vec<(f64, String)>,
+    ) -> Result<(), WeightError> {
+        self.production_rules
+            .push(Production::stochastic(token, successors)?);
+        Ok(())
+    }
+
+    /// Registers a parametric rule such as `A(x, y) : x > 1 -> A(x*0.5, y+1) F(x)`,
+    /// see [`Production::parametric`].
+    pub fn register_parametric_rule(&mut self, rule: &str) -> Result<(), ParseError> {
+        self.production_rules.push(Production::parametric(rule)?);
+        Ok(())
+    }
+
+    /// Registers a production that decomposes modules right after they're
+    /// produced, within the same step.
+    pub fn register_decomposition_rule(&mut self, production: Production) {
+        self.decomposition_rules.push(production);
+    }
+
+    /// Registers a production that is only applied when the word is executed,
+    /// such as one expanding a leaf module into the turtle commands drawing it.
+    pub fn register_interpretation_rule(&mut self, production: Production) {
+        self.interpretation_rules.push(production);
+    }
+
+    /// Adds a named table of productions, replacing any with the same name.
+    pub fn register_table(&mut self, name: String, production_rules: Vec<Production>) {
+        self.tables.insert(name, production_rules);
+    }
+
+    /// Chooses the table for every generation from its number.
+    pub fn set_schedule(
+        &mut self,
+        schedule: impl 'static + Send + Sync + Fn(usize) -> Option<String>,
+    ) {
+        self.schedule = Some(Box::new(schedule));
+    }
+
+    /// The name of the table that rewrites `generation`.
+    pub fn table_for(&self, generation: usize) -> Option<String> {
+        match &self.schedule {
+            Some(schedule) => schedule(generation),
+            None => self.table.clone(),
+        }
+    }
+
+    /// The productions of the named table, or the `production_rules` for `None`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no table with that name.
+    pub fn rules_mut(&mut self, table: Option<&str>) -> &mut Vec<Production> {
+        table_rules(&mut self.production_rules, &mut self.tables, table)
+    }
+
+    /// Rewrites every module of the axiom in parallel. The axiom is scanned once
+    /// from left to right and at each position the production with the longest
+    /// matching token is applied, ties going to the earliest registered. Modules
+    /// no production matches are copied unchanged.
+    ///
+    /// The productions come from the table picked by [`LSystem::table_for`].
     pub fn step(&mut self) {
-        let mut old_axiom = vec![(0, self.axiom.clone())];
-        let mut new_axiom = vec![];
-
-        for (token, replacement) in self.production_rules.iter_mut() {
-            let mut old_axiom_extend = vec![];
-
-            for (i, part) in &mut old_axiom {
-                for j in part
-                    .match_indices(&*token)
-                    .map(|p| p.0)
-                    .collect::<Vec<_>>()
-                    .into_iter()
-                    .rev()
-                {
-                    new_axiom.push((*i + j, (replacement)()));
-
-                    old_axiom_extend.push((*i + j + token.len(), part.split_off(j + token.len())));
-                    part.truncate(part.len() - token.len());
-                }
-            }
+        let table = self.table_for(self.generation);
+        self.step_with(table.as_deref());
+    }
+
+    /// Takes a step with the named table, or `production_rules` for `None`,
+    /// regardless of the schedule.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no table with that name.
+    pub fn step_with(&mut self, table: Option<&str>) {
+        let mut guard = Guard::new(Limits::default());
+        self.derive(table, |pass, word, rng, trace| {
+            pass.rewrite(word, rng, &mut guard, trace)
+        })
+        .expect("steps without limits can't fail");
+    }
+
+    // Replaces the axiom with the next generation, rewriting it with the
+    // productions and then the decomposition rules by calling `rewrite` for
+    // each pass, unless it fails.
+    pub(crate) fn derive(
+        &mut self,
+        table: Option<&str>,
+        mut rewrite: impl FnMut(
+            &Pass,
+            &[Module],
+            &mut ChaCha8Rng,
+            Option<&mut Vec<Application>>,
+        ) -> Result<(Word, bool), LimitError>,
+    ) -> Result<(), LimitError> {
+        let rng = self.history.as_ref().map(|_| self.rng.clone());
+        let mut passes = vec![];
+
+        let rules = table_rules(&mut self.production_rules, &mut self.tables, table);
+        let pass = Pass::new(rules, self.cut, &self.ignore, self.generation);
+        let (mut axiom, _) = rewrite(
+            &pass,
+            &self.axiom,
+            &mut self.rng,
+            next_pass(&mut passes, rng.is_some()),
+        )?;
 
-            old_axiom.extend_from_slice(&old_axiom_extend);
+        let pass = Pass::new(
+            &self.decomposition_rules,
+            None,
+            &self.ignore,
+            self.generation,
+        );
+        for _ in 0..self.decomposition_depth {
+            let (decomposed, rewritten) = rewrite(
+                &pass,
+                &axiom,
+                &mut self.rng,
+                next_pass(&mut passes, rng.is_some()),
+            )?;
+            axiom = decomposed;
+            if !rewritten {
+                break;
+            }
         }
 
-        new_axiom.extend_from_slice(&old_axiom);
-        new_axiom.sort_by(|a, b| a.0.cmp(&b.0));
-        self.axiom = new_axiom.into_iter().map(|a| a.1).collect();
+        if let (Some(history), Some(rng)) = (&mut self.history, rng) {
+            let step = Step::new(table, passes, rng, self.rng.clone());
+            history.push(self.generation, &self.axiom, &axiom, step);
+        }
+        self.axiom = axiom;
+        self.generation += 1;
+        Ok(())
     }
 
     pub fn step_by(&mut self, n: usize) {
         (0..n).for_each(|_| self.step())
     }
+
+    /// Lazily yields the modules of the generation `n` steps after the current
+    /// one, see [`Expansion`]. Systems with decomposition rules aren't
+    /// supported.
+    pub fn expand(&mut self, n: usize) -> Result<Expansion<'_>, AnalysisError> {
+        if !self.decomposition_rules.is_empty() {
+            return Err(AnalysisError::Decomposition);
+        }
+        Ok(Expansion::new(self, n))
+    }
+}
+
+// A left to right pass over a word with one set of productions.
+pub(crate) struct Pass<'a> {
+    rules: &'a [Production],
+    candidates: Candidates,
+    cut: Option<Symbol>,
+    ignore: &'a Word,
+    generation: usize,
 }
 
+impl<'a> Pass<'a> {
+    fn new(
+        rules: &'a [Production],
+        cut: Option<Symbol>,
+        ignore: &'a Word,
+        generation: usize,
+    ) -> Self {
+        Self {
+            rules,
+            candidates: Candidates::new(rules),
+            cut,
+            ignore,
+            generation,
+        }
+    }
+
+    // Rewrites `word`, also returning whether any production applied.
+    fn rewrite(
+        &self,
+        word: &[Module],
+        rng: &mut ChaCha8Rng,
+        guard: &mut Guard,
+        trace: Option<&mut Vec<Application>>,
+    ) -> Result<(Word, bool), LimitError> {
+        guard.start_pass();
+        let (new_word, _, rewritten_any) =
+            self.rewrite_span(word, 0, word.len(), rng, guard, trace)?;
+        Ok((new_word, rewritten_any))
+    }
+
+    // Rewrites `word` from `start` up to the first module at or past `end` that
+    // isn't part of a match, returning the new modules, the index it stopped
+    // at and whether any production applied. Applications are traced with
+    // targets relative to the new modules.
+    pub(crate) fn rewrite_span(
+        &self,
+        word: &[Module],
+        start: usize,
+        end: usize,
+        rng: &mut ChaCha8Rng,
+        guard: &mut Guard,
+        mut trace: Option<&mut Vec<Application>>,
+    ) -> Result<(Word, usize, bool), LimitError> {
+        let mut rewritten_any = false;
+        let mut new_word = Word(Vec::with_capacity(end - start));
+        let mut i = start;
+
+        while i < end {
+            let start = (i, new_word.len());
+            let mut production = None;
+            let mut draw = None;
+            if Some(word[i].symbol) == self.cut {
+                i = branch_end(word, i);
+                rewritten_any = true;
+            } else if let Some((successor, len, r, drawn)) =
+                self.candidates
+                    .apply(self.rules, word, i, self.ignore, self.generation, rng)
+            {
+                new_word.extend(successor.0);
+                rewritten_any = true;
+                i += len;
+                production = Some(r);
+                draw = drawn;
+            } else {
+                new_word.push(word[i].clone());
+                guard.check(&new_word, &word[i..=i])?;
+                i += 1;
+                continue;
+            }
+            guard.check(&new_word, &new_word[start.1..])?;
+
+            if let Some(trace) = trace.as_deref_mut() {
+                trace.push(Application {
+                    production,
+                    source: start.0..i,
+                    target: start.1..new_word.len(),
+                    draw,
+                    replaced: Word(word[start.0..i].to_vec()),
+                    successor: Word(new_word[start.1..].to_vec()),
+                });
+            }
+        }
+
+        Ok((new_word, i, rewritten_any))
+    }
+}
+
+// A list for the applications of another pass, when recording them.
+fn next_pass(passes: &mut Vec<Vec<Application>>, recording: bool) -> Option<&mut Vec<Application>> {
+    if !recording {
+        return None;
+    }
+    passes.push(vec![]);
+    passes.last_mut()
+}
+
+// The index of the bracket closing the branch `word[i]` is in, or the length
+// of the word if it's not in one.
+fn branch_end(word: &[Module], i: usize) -> usize {
+    let mut depth = 0;
+    for (j, module) in word.iter().enumerate().skip(i + 1) {
+        if module.symbol == PUSH {
+            depth += 1;
+        } else if module.symbol == POP {
+            if depth == 0 {
+                return j;
+            }
+            depth -= 1;
+        }
+    }
+    word.len()
+}
+
+pub(crate) fn table_rules<'a>(
+    production_rules: &'a mut Vec<Production>,
+    tables: &'a mut HashMap<String, Vec<Production>>,
+    table: Option<&str>,
+) -> &'a mut Vec<Production> {
+    match table {
+        Some(name) => tables
+            .get_mut(name)
+            .unwrap_or_else(|| panic!("there is no production table named `{}`", name)),
+        None => production_rules,
+    }
+}
+
+// Indexes productions by the first character of their token, longest token first.
+pub(crate) struct Candidates(HashMap<char, Vec<usize>>);
+
+impl Candidates {
+    pub fn new(rules: &[Production]) -> Self {
+        let mut candidates: HashMap<char, Vec<usize>> = HashMap::new();
+        for (i, rule) in rules.iter().enumerate() {
+            if let Some(first) = rule.token.chars().next() {
+                candidates.entry(first).or_default().push(i);
+            }
+        }
+        for candidates in candidates.values_mut() {
+            candidates.sort_by_key(|i| std::cmp::Reverse(rules[*i].token.len()));
+        }
+        Self(candidates)
+    }
+
+    /// Applies the production that matches at `i`, returning its replacement
+    /// and the number of modules it replaces.
+    pub fn rewrite(
+        &self,
+        rules: &[Production],
+        word: &[Module],
+        i: usize,
+        ignore: &Word,
+        generation: usize,
+        rng: &mut ChaCha8Rng,
+    ) -> Option<(Word, usize)> {
+        self.apply(rules, word, i, ignore, generation, rng)
+            .map(|(successor, len, ..)| (successor, len))
+    }
+
+    /// Like [`Candidates::rewrite`], also returning the index of the production
+    /// and the number drawn to pick its successor.
+    pub fn apply(
+        &self,
+        rules: &[Production],
+        word: &[Module],
+        i: usize,
+        ignore: &Word,
+        generation: usize,
+        rng: &mut ChaCha8Rng,
+    ) -> Option<(Word, usize, usize, Option<f64>)> {
+        let candidates = self.0.get(&word[i].symbol.first_char()?)?;
+        let (r, args, len) = candidates.iter().find_map(|r| {
+            let (args, len) = rules[*r].match_parameters(&word[i..])?;
+            if rules[*r].context_matches(word, i, i + len, ignore) {
+                Some((*r, args, len))
+            } else {
+                None
+            }
+        })?;
+
+        let Production {
+            token, successors, ..
+        } = &rules[r];
+        let matched = Match {
+            token,
+            args: &args,
+            index: i,
+            len,
+            generation,
+            word,
+        };
+        let (successor, draw) = replace(successors, &matched, rng);
+        Some((successor, len, r, draw))
+    }
+}
+
+// Applies the interpretation rules of a system while executing it. The rng is
+// a copy, so interpreting doesn't change later derivations.
+struct Interpreter<'a> {
+    system: &'a LSystem,
+    candidates: Candidates,
+    rng: ChaCha8Rng,
+    depth: usize,
+}
+
+impl<'a> Interpreter<'a> {
+    fn new(system: &'a LSystem) -> Self {
+        Self {
+            system,
+            candidates: Candidates::new(&system.interpretation_rules),
+            rng: system.rng.clone(),
+            depth: system.interpretation_depth,
+        }
+    }
+
+    fn rewrite(&mut self, word: &[Module], i: usize) -> Option<(Word, usize)> {
+        if self.depth == 0 {
+            return None;
+        }
+        self.candidates.rewrite(
+            &self.system.interpretation_rules,
+            word,
+            i,
+            &self.system.ignore,
+            self.system.generation,
+            &mut self.rng,
+        )
+    }
+}
+
+/// Reads the parameters of a query module off the executor's state.
+pub type Query<State> = Box<dyn FnMut(&State) -> Vec<Value>>;
+
 pub struct LSystemExecutor<State> {
     pub state: State,
     execution_rules: Vec<(String, Box<dyn CallParsedErased<State>>)>,
+    query_rules: Vec<(String, Query<State>)>,
 }
 
 impl<State: 'static> LSystemExecutor<State> {
@@ -137,11 +953,16 @@ impl<State: 'static> LSystemExecutor<State> {
         Self {
             state,
             execution_rules: vec![],
+            query_rules: vec![],
         }
     }
 
     pub fn used_tokens(&self) -> Vec<&str> {
-        self.execution_rules.iter().map(|a| a.0.as_str()).collect()
+        self.execution_rules
+            .iter()
+            .map(|a| a.0.as_str())
+            .chain(self.query_rules.iter().map(|q| q.0.as_str()))
+            .collect()
     }
 
     pub fn register_rule<T: 'static>(
@@ -158,35 +979,138 @@ impl<State: 'static> LSystemExecutor<State> {
         ));
     }
 
+    /// Registers a query module such as ABOP's `?P`. When the word is executed
+    /// with [`execute_mut`](Self::execute_mut), the parameters of the last
+    /// module the token spans are replaced with what `query` reads off the
+    /// state at that point, so the next step can use them in guards.
+    pub fn register_query_rule(
+        &mut self,
+        token: String,
+        query: impl 'static + FnMut(&State) -> Vec<Value>,
+    ) {
+        self.query_rules.push((token, Box::new(query)));
+    }
+
+    /// Executes the system's word, expanding it with the interpretation rules
+    /// of the system on the way.
     pub fn execute(&mut self, system: &LSystem) -> Result<(), serde_json::Error> {
-        let mut instructions = system.axiom.clone();
-        while instructions.len() != 0 {
-            if let Some((token, rule)) = self
-                .execution_rules
-                .iter_mut()
-                .find(|e| instructions.starts_with(&e.0))
-            {
-                let _: String = instructions.drain(..token.len()).collect();
-                if instructions.chars().next() != Some('(') {
-                    rule.call_parsed(&mut self.state, "".into())?;
+        let mut interpreter = Interpreter::new(system);
+        self.execute_interpreted(&system.axiom, Some(&mut interpreter), None)
+    }
+
+    /// Like [`execute`](Self::execute), but also answers the query modules of
+    /// the system's word, see [`register_query_rule`](Self::register_query_rule).
+    /// Query modules produced by interpretation rules are not answered.
+    pub fn execute_mut(&mut self, system: &mut LSystem) -> Result<(), serde_json::Error> {
+        let mut answers = vec![];
+        let mut interpreter = Interpreter::new(system);
+        self.execute_interpreted(&system.axiom, Some(&mut interpreter), Some(&mut answers))?;
+        drop(interpreter);
+
+        for (i, params) in answers {
+            system.axiom[i].params = params;
+        }
+        Ok(())
+    }
+
+    /// Like [`execute_word`](Self::execute_word), but query modules are answered
+    /// before any other rule is tried at their position.
+    pub fn execute_word_mut(&mut self, word: &mut [Module]) -> Result<(), serde_json::Error> {
+        let mut answers = vec![];
+        self.execute_interpreted(word, None, Some(&mut answers))?;
+        for (i, params) in answers {
+            word[i].params = params;
+        }
+        Ok(())
+    }
+
+    // Executes `word`, replacing modules with their interpretation while the
+    // interpreter has depth left. Query answers are collected by index into
+    // `word`, if asked for.
+    fn execute_interpreted(
+        &mut self,
+        word: &[Module],
+        mut interpreter: Option<&mut Interpreter>,
+        mut answers: Option<&mut Vec<(usize, Vec<Value>)>>,
+    ) -> Result<(), serde_json::Error> {
+        let mut i = 0;
+        while i < word.len() {
+            if let Some(interpreter) = interpreter.as_deref_mut() {
+                if let Some((successor, len)) = interpreter.rewrite(word, i) {
+                    interpreter.depth -= 1;
+                    let executed = self.execute_interpreted(&successor, Some(interpreter), None);
+                    interpreter.depth += 1;
+                    executed?;
+                    i += len;
                     continue;
                 }
+            }
 
-                for i in 0..instructions.len() {
-                    let res = rule.call_parsed(&mut self.state, instructions[..=i].to_string());
-                    if res.is_ok() {
-                        let _: String = instructions.drain(..=i).collect();
-                        break;
-                    }
-                    if i == instructions.len() - 1 {
-                        res?;
-                    }
+            if let Some(answers) = &mut answers {
+                let matched = self
+                    .query_rules
+                    .iter_mut()
+                    .find_map(|(token, query)| Some((match_token(&word[i..], token)?, query)));
+                if let Some((len, query)) = matched {
+                    answers.push((i + len - 1, query(&self.state)));
+                    i += len;
+                    continue;
                 }
-            } else {
-                instructions.remove(0);
             }
+
+            i += self.execute_first(&word[i..])?;
         }
 
         Ok(())
     }
+
+    /// Runs the first registered rule whose token matches at each position of
+    /// `word`, passing it the parameters of the last module the token spans.
+    /// Modules no rule matches are skipped.
+    pub fn execute_word(&mut self, word: &[Module]) -> Result<(), serde_json::Error> {
+        let mut i = 0;
+        while i < word.len() {
+            i += self.execute_first(&word[i..])?;
+        }
+
+        Ok(())
+    }
+
+    /// Like [`execute_word`](Self::execute_word), but only buffers as many
+    /// modules as the longest token can span, so it can run over an
+    /// [`Expansion`] without holding the whole word.
+    pub fn execute_iter(
+        &mut self,
+        modules: impl IntoIterator<Item = Module>,
+    ) -> Result<(), serde_json::Error> {
+        let lookahead = self.execution_rules.iter().map(|r| r.0.len()).max();
+        let lookahead = lookahead.unwrap_or(1).max(1);
+        let mut modules = modules.into_iter();
+        let mut buffer = VecDeque::with_capacity(lookahead);
+
+        loop {
+            buffer.extend(modules.by_ref().take(lookahead - buffer.len()));
+            if buffer.is_empty() {
+                return Ok(());
+            }
+            let len = self.execute_first(buffer.make_contiguous())?;
+            buffer.drain(..len);
+        }
+    }
+
+    // Runs the rule matching at the start of `modules`, returning how many
+    // modules were consumed.
+    fn execute_first(&mut self, modules: &[Module]) -> Result<usize, serde_json::Error> {
+        let matched = self
+            .execution_rules
+            .iter_mut()
+            .find_map(|(token, rule)| Some((match_token(modules, token)?, rule)));
+
+        if let Some((len, rule)) = matched {
+            rule.call_parsed(&mut self.state, &modules[len - 1].params)?;
+            Ok(len)
+        } else {
+            Ok(1)
+        }
+    }
 }
@@ -0,0 +1,136 @@
+//! Limits that keep a step from growing a word without bound, see
+//! [`LSystem::try_step`].
+
+use crate::word::Module;
+use crate::LSystem;
+use serde_json::Value;
+use std::fmt;
+use std::mem::size_of;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// The most modules a word may have.
+    pub max_len: Option<usize>,
+    /// The most bytes a word may take up, as estimated by [`word_bytes`].
+    pub max_bytes: Option<usize>,
+    /// The longest a single step may take. Time is only measured when this is
+    /// set, since [`Instant`] isn't available on every target.
+    pub max_duration: Option<Duration>,
+}
+
+/// The limit a step went past, holding the value it was set to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LimitError {
+    Length(usize),
+    Bytes(usize),
+    Duration(Duration),
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Length(limit) => write!(f, "the word grew past {} modules", limit),
+            Self::Bytes(limit) => write!(f, "the word grew past {} bytes", limit),
+            Self::Duration(limit) => write!(f, "the step took longer than {:?}", limit),
+        }
+    }
+}
+
+impl std::error::Error for LimitError {}
+
+/// An estimate of the memory a word takes up, counting its modules and their
+/// parameters but not what the parameters point to.
+pub fn word_bytes(word: &[Module]) -> usize {
+    word.iter().map(module_bytes).sum()
+}
+
+fn module_bytes(module: &Module) -> usize {
+    size_of::<Module>() + module.params.len() * size_of::<Value>()
+}
+
+// Checks the limits as a pass writes its word.
+pub(crate) struct Guard {
+    limits: Limits,
+    started: Option<Instant>,
+    bytes: usize,
+    checks: usize,
+}
+
+impl Guard {
+    pub(crate) fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            started: limits.max_duration.map(|_| Instant::now()),
+            bytes: 0,
+            checks: 0,
+        }
+    }
+
+    // Resets the count of bytes for a new word.
+    pub(crate) fn start_pass(&mut self) {
+        self.bytes = 0;
+    }
+
+    #[cfg(feature = "rayon")]
+    pub(crate) fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    // Checks a word that has had `added` pushed onto its end.
+    pub(crate) fn check(&mut self, word: &[Module], added: &[Module]) -> Result<(), LimitError> {
+        if let Some(limit) = self.limits.max_len.filter(|l| word.len() > *l) {
+            return Err(LimitError::Length(limit));
+        }
+        if let Some(limit) = self.limits.max_bytes {
+            self.bytes += word_bytes(added);
+            if self.bytes > limit {
+                return Err(LimitError::Bytes(limit));
+            }
+        }
+        // Reading the clock is slow next to copying a module.
+        self.checks += 1;
+        if self.checks % 256 == 0 {
+            self.check_duration()?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_duration(&self) -> Result<(), LimitError> {
+        match (self.started, self.limits.max_duration) {
+            (Some(started), Some(limit)) if started.elapsed() > limit => {
+                Err(LimitError::Duration(limit))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl LSystem {
+    /// Takes a step like [`LSystem::step`], failing if the word goes past any
+    /// of the `limits`. The system is left at the last generation on failure.
+    pub fn try_step(&mut self) -> Result<(), LimitError> {
+        let table = self.table_for(self.generation);
+        self.try_step_with(table.as_deref())
+    }
+
+    /// Takes a step like [`LSystem::step_with`] within the `limits`, see
+    /// [`LSystem::try_step`].
+    pub fn try_step_with(&mut self, table: Option<&str>) -> Result<(), LimitError> {
+        let rng = self.rng.clone();
+        let mut guard = Guard::new(self.limits);
+        let result = self.derive(table, |pass, word, rng, trace| {
+            pass.rewrite(word, rng, &mut guard, trace)
+        });
+        if result.is_err() {
+            self.rng = rng;
+        }
+        result
+    }
+
+    /// Takes up to `n` steps within the `limits`, stopping at the first that
+    /// goes past them.
+    pub fn try_step_by(&mut self, n: usize) -> Result<(), LimitError> {
+        (0..n).try_for_each(|_| self.try_step())
+    }
+}
@@ -0,0 +1,229 @@
+//! Finds mistakes in a system and the executor that runs it, such as rules
+//! that can never apply.
+
+use crate::word::{Symbol, Word, POP, PUSH};
+use crate::{LSystem, LSystemExecutor, Match, Production};
+use std::collections::BTreeSet;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Lint {
+    /// A symbol of the axiom or a successor that no production or executor
+    /// rule handles, so executing skips it.
+    Unhandled(Symbol),
+    /// A production whose token has symbols that never occur in a word derived
+    /// from the axiom. These aren't reported when a reachable production can't
+    /// be analysed.
+    Unreachable(String),
+    /// A rule that never applies, because an earlier production with the same
+    /// token always does, or an earlier executor rule's token is a prefix of
+    /// its own. `by` is the index of the earlier rule among the productions of
+    /// the same set, or among the executor's [`used_tokens`].
+    ///
+    /// [`used_tokens`]: LSystemExecutor::used_tokens
+    Shadowed { token: String, by: usize },
+    /// Two productions where the last symbols of `first` are the first symbols
+    /// of `second`, so matching one can keep the other from matching.
+    Overlap { first: String, second: String },
+    /// The axiom, for `None`, or a successor of the production with the token
+    /// has brackets that don't balance.
+    UnbalancedBrackets(Option<String>),
+    /// A production whose successors are closures that read their match, see
+    /// [`Production::reads_match`], so what it produces is left out.
+    NotAnalysable(String),
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Unhandled(s) => write!(f, "nothing handles the symbol `{}`", s),
+            Self::Unreachable(t) => write!(f, "the production for `{}` is unreachable", t),
+            Self::Shadowed { token, by } => {
+                write!(f, "the rule for `{}` is shadowed by rule {}", token, by)
+            }
+            Self::Overlap { first, second } => {
+                write!(f, "the tokens `{}` and `{}` overlap", first, second)
+            }
+            Self::UnbalancedBrackets(None) => f.write_str("the axiom has unbalanced brackets"),
+            Self::UnbalancedBrackets(Some(t)) => {
+                write!(f, "a successor of `{}` has unbalanced brackets", t)
+            }
+            Self::NotAnalysable(t) => write!(f, "the production for `{}` can't be analysed", t),
+        }
+    }
+}
+
+// The symbols a token is made of.
+fn symbols(token: &str) -> Vec<Symbol> {
+    Word::from(token).iter().map(|m| m.symbol).collect()
+}
+
+fn balanced(symbols: &[Symbol]) -> bool {
+    let mut depth = 0usize;
+    for symbol in symbols {
+        if *symbol == PUSH {
+            depth += 1;
+        } else if *symbol == POP {
+            match depth.checked_sub(1) {
+                Some(d) => depth = d,
+                None => return false,
+            }
+        }
+    }
+    depth == 0
+}
+
+// The symbols each successor of a production produces, calling successors that
+// don't read their match with one made up for the token.
+fn successor_symbols(production: &Production) -> Option<Vec<Vec<Symbol>>> {
+    if production.successor_symbols.is_some() || production.reads_match {
+        return production.successor_symbols.clone();
+    }
+    let word = Word::from(production.token.as_str());
+    let matched = Match {
+        token: &production.token,
+        args: &[],
+        index: 0,
+        len: word.len(),
+        generation: 0,
+        word: &word,
+    };
+    let successors = production.successors.iter();
+    Some(
+        successors
+            .map(|(_, successor)| successor(&matched).iter().map(|m| m.symbol).collect())
+            .collect(),
+    )
+}
+
+/// Lints a system along with the executor that runs it. Successors that read
+/// their [`Match`] aren't called, so productions made of those are reported as
+/// [`Lint::NotAnalysable`] and otherwise left out. The others are called once
+/// and assumed to always give the same replacement.
+pub fn lint<State: 'static>(system: &LSystem, executor: &LSystemExecutor<State>) -> Vec<Lint> {
+    let mut lints = vec![];
+    let mut rule_sets: Vec<&[Production]> =
+        vec![&system.production_rules, &system.decomposition_rules];
+    let mut tables: Vec<_> = system.tables.iter().collect();
+    tables.sort_by(|a, b| a.0.cmp(b.0));
+    rule_sets.extend(tables.into_iter().map(|(_, rules)| rules.as_slice()));
+    rule_sets.push(&system.interpretation_rules);
+
+    let analysed: Vec<(&Production, Option<Vec<Vec<Symbol>>>)> = rule_sets
+        .iter()
+        .flat_map(|rules| rules.iter())
+        .map(|p| (p, successor_symbols(p)))
+        .collect();
+    lints.extend(
+        analysed
+            .iter()
+            .filter(|(_, symbols)| symbols.is_none())
+            .map(|(p, _)| Lint::NotAnalysable(p.token.clone())),
+    );
+    let productions: Vec<(&Production, &[Vec<Symbol>])> = analysed
+        .iter()
+        .map(|(p, symbols)| (*p, symbols.as_deref().unwrap_or_default()))
+        .collect();
+
+    let axiom: Vec<Symbol> = system.axiom.iter().map(|m| m.symbol).collect();
+    if !balanced(&axiom) {
+        lints.push(Lint::UnbalancedBrackets(None));
+    }
+    for (production, successors) in &productions {
+        if !successors.iter().all(|s| balanced(s)) {
+            lints.push(Lint::UnbalancedBrackets(Some(production.token.clone())));
+        }
+    }
+
+    // Symbols are handled by the tokens they are part of.
+    let executed = executor.used_tokens();
+    let mut handled: BTreeSet<Symbol> = productions
+        .iter()
+        .map(|(p, _)| p.token.as_str())
+        .chain(executed.iter().copied())
+        .flat_map(symbols)
+        .collect();
+    handled.extend(system.cut);
+    let mut unhandled = BTreeSet::new();
+    for word in std::iter::once(&axiom).chain(productions.iter().flat_map(|(_, s)| *s)) {
+        unhandled.extend(word.iter().filter(|s| !handled.contains(s)));
+    }
+    lints.extend(unhandled.into_iter().map(Lint::Unhandled));
+
+    let mut reachable: BTreeSet<Symbol> = axiom.iter().copied().collect();
+    let mut applied = vec![false; productions.len()];
+    loop {
+        let before = reachable.len();
+        for (i, (production, successors)) in productions.iter().enumerate() {
+            if !applied[i]
+                && symbols(&production.token)
+                    .iter()
+                    .all(|s| reachable.contains(s))
+            {
+                applied[i] = true;
+                reachable.extend(successors.iter().flatten());
+            }
+        }
+        if reachable.len() == before {
+            break;
+        }
+    }
+    // A production that can't be analysed may produce any symbol.
+    let complete = analysed
+        .iter()
+        .zip(&applied)
+        .all(|((_, symbols), applied)| !applied || symbols.is_some());
+    if complete {
+        for (i, (production, _)) in productions.iter().enumerate() {
+            if !applied[i] {
+                lints.push(Lint::Unreachable(production.token.clone()));
+            }
+        }
+    }
+
+    for rules in rule_sets {
+        for (i, later) in rules.iter().enumerate() {
+            let shadowing = rules[..i].iter().position(|earlier| {
+                earlier.token == later.token
+                    && earlier.condition.is_none()
+                    && earlier.arity.is_none()
+                    && earlier.left_context.is_none()
+                    && earlier.right_context.is_none()
+            });
+            if let Some(by) = shadowing {
+                lints.push(Lint::Shadowed {
+                    token: later.token.clone(),
+                    by,
+                });
+            }
+        }
+
+        for first in rules {
+            for second in rules {
+                let (a, b) = (symbols(&first.token), symbols(&second.token));
+                let overlaps = (1..a.len().min(b.len())).any(|n| a[a.len() - n..] == b[..n]);
+                if overlaps {
+                    lints.push(Lint::Overlap {
+                        first: first.token.clone(),
+                        second: second.token.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    for (i, later) in executed.iter().enumerate() {
+        let later_symbols = symbols(later);
+        if let Some(by) = executed[..i]
+            .iter()
+            .position(|earlier| later_symbols.starts_with(&symbols(earlier)))
+        {
+            lints.push(Lint::Shadowed {
+                token: later.to_string(),
+                by,
+            });
+        }
+    }
+
+    lints
+}
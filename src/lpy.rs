@@ -0,0 +1,570 @@
+//! Converts between [`LSystemSpec`] and the subset of L-Py's syntax below.
+//!
+//! ```text
+//! # constants come before the rules
+//! scale = 0.5
+//!
+//! Axiom: A(1)
+//! derivation length: 4
+//! ignore: +-
+//!
+//! production:
+//! A(x) --> F(x) [+(30) A(x * scale)] A(x + 1)
+//! B < C(x) > D :
+//!     if x > 1 and not x == 3:
+//!         produce C(x ** 2)
+//!     produce E
+//!
+//! homomorphism:
+//! E --> F(2)
+//! endlsystem
+//! ```
+//!
+//! Modules must have single character names, predecessors are a single module
+//! and contexts can't bind parameters. Conditions and parameters may use
+//! `and`, `or`, `not`, `**`, `True` and `False` besides the operators of
+//! [`Expr`](crate::expr::Expr). `decomposition:` and `interpretation:`
+//! sections are read too, the latter being another name for `homomorphism:`.
+
+use crate::lsys::GrammarError;
+use crate::spec::{LSystemSpec, ProductionSpec, SpecError, SuccessorSpec};
+use crate::turtle::BasicTurtle;
+use crate::{CallParsed, LSystemExecutor};
+use serde_json::Value;
+use std::f64::consts::PI;
+use std::fmt;
+use std::marker::PhantomData;
+
+/// A model read from L-Py, or to be written as one.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LpyModel {
+    pub spec: LSystemSpec,
+    pub derivation_length: usize,
+}
+
+/// A spec that uses features L-Py's syntax subset has no way to write.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExportError {
+    pub field: String,
+    pub message: String,
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Production,
+    Decomposition,
+    Interpretation,
+}
+
+// The predecessor of a block whose `produce` statements are being read.
+struct Block {
+    predecessor: ProductionSpec,
+    indent: usize,
+    condition: Option<(String, usize)>,
+}
+
+/// Reads an L-Py model.
+pub fn parse(source: &str) -> Result<LpyModel, GrammarError> {
+    let mut model = LpyModel::default();
+    let mut names = vec![];
+    let mut values = vec![];
+    let mut section = None;
+    let mut block: Option<Block> = None;
+
+    for (i, line) in source.lines().enumerate() {
+        let error = |column: usize, message: String| GrammarError {
+            message,
+            line: i + 1,
+            column: line[..column].chars().count() + 1,
+        };
+        let content = line.split('#').next().unwrap().trim_end();
+        let indent = content.len() - content.trim_start().len();
+        let content = content.trim_start();
+        if content.is_empty() {
+            continue;
+        }
+
+        if let Some(current) = &mut block {
+            if indent > current.indent {
+                let spec =
+                    read_statement(current, content, indent).map_err(|(c, m)| error(c, m))?;
+                if let Some(spec) = spec {
+                    push(&mut model.spec, section, spec, &names, &values)
+                        .map_err(|e| error(indent, e.to_string()))?;
+                }
+                continue;
+            }
+            block = None;
+        }
+
+        let lower = content.to_lowercase();
+        if let Some(rest) = lower.strip_prefix("axiom:") {
+            let axiom = &content[content.len() - rest.len()..];
+            model.spec.axiom = python_to_expr(axiom.trim());
+        } else if let Some(rest) = lower.strip_prefix("derivation length:") {
+            model.derivation_length = rest
+                .trim()
+                .parse()
+                .map_err(|_| error(indent, "expected a number of steps".into()))?;
+        } else if let Some(rest) = lower.strip_prefix("ignore:") {
+            let symbols = &content[content.len() - rest.len()..];
+            model.spec.ignore = symbols.split_whitespace().collect();
+        } else if lower == "production:" {
+            section = Some(Section::Production);
+        } else if lower == "decomposition:" {
+            section = Some(Section::Decomposition);
+        } else if lower == "homomorphism:" || lower == "interpretation:" {
+            section = Some(Section::Interpretation);
+        } else if lower == "endlsystem" {
+            section = None;
+        } else if section.is_none() {
+            let equals = content
+                .find('=')
+                .ok_or_else(|| error(indent, "expected `name = value`".into()))?;
+            let name = content[..equals].trim().to_string();
+            let expr = crate::expr::Expr::parse(&python_to_expr(&content[equals + 1..]), &names)
+                .map_err(|e| error(indent + equals + 1, e.message))?;
+            values.push(expr.eval(&values));
+            model
+                .spec
+                .constants
+                .insert(name.clone(), *values.last().unwrap());
+            names.push(name);
+        } else if let Some(arrow) = content.find("-->") {
+            let mut spec = predecessor(&content[..arrow]).map_err(|m| error(indent, m))?;
+            spec.successor = SuccessorSpec::Template(python_to_expr(content[arrow + 3..].trim()));
+            push(&mut model.spec, section, spec, &names, &values)
+                .map_err(|e| error(indent + arrow + 3, e.to_string()))?;
+        } else if let Some(head) = content.strip_suffix(':') {
+            let predecessor = predecessor(head).map_err(|m| error(indent, m))?;
+            block = Some(Block {
+                predecessor,
+                indent,
+                condition: None,
+            });
+        } else {
+            return Err(error(indent, "expected a production".into()));
+        }
+    }
+
+    Ok(model)
+}
+
+// Reads a statement of a block, giving the production it makes, if any. Errors
+// carry the byte column along with the message.
+fn read_statement(
+    block: &mut Block,
+    statement: &str,
+    indent: usize,
+) -> Result<Option<ProductionSpec>, (usize, String)> {
+    if block
+        .condition
+        .as_ref()
+        .is_some_and(|(_, if_indent)| indent <= *if_indent)
+    {
+        block.condition = None;
+    }
+
+    let (condition, produce) = match statement.strip_prefix("if ") {
+        Some(rest) => {
+            let colon = rest
+                .rfind(':')
+                .ok_or_else(|| (indent, "expected `:` after the condition".to_string()))?;
+            let condition = python_to_expr(rest[..colon].trim());
+            let body = rest[colon + 1..].trim();
+            if body.is_empty() {
+                block.condition = Some((condition, indent));
+                return Ok(None);
+            }
+            (Some(condition), body)
+        }
+        None => (block.condition.as_ref().map(|c| c.0.clone()), statement),
+    };
+
+    let successor = produce
+        .strip_prefix("produce")
+        .ok_or_else(|| (indent, "expected `produce` or `if`".to_string()))?;
+    let mut spec = block.predecessor.clone();
+    spec.condition = condition;
+    spec.successor = SuccessorSpec::Template(python_to_expr(successor.trim()));
+    Ok(Some(spec))
+}
+
+// Checks that the production builds before adding it to the section.
+fn push(
+    spec: &mut LSystemSpec,
+    section: Option<Section>,
+    production: ProductionSpec,
+    names: &[String],
+    values: &[f64],
+) -> Result<(), SpecError> {
+    let (name, productions) = match section {
+        Some(Section::Production) | None => ("productions", &mut spec.productions),
+        Some(Section::Decomposition) => ("decomposition", &mut spec.decomposition),
+        Some(Section::Interpretation) => ("interpretation", &mut spec.interpretation),
+    };
+    let field = format!("{}[{}]", name, productions.len());
+    production.build(names, values, &field)?;
+    productions.push(production);
+    Ok(())
+}
+
+// Reads `left < predecessor > right`, dropping the parameters of contexts.
+fn predecessor(text: &str) -> Result<ProductionSpec, String> {
+    let (left, rest) = match text.find('<') {
+        Some(i) => (Some(&text[..i]), &text[i + 1..]),
+        None => (None, text),
+    };
+    let (predecessor, right) = match rest.find('>') {
+        Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+        None => (rest, None),
+    };
+
+    let predecessor = predecessor.trim();
+    let name = predecessor.split('(').next().unwrap().trim();
+    if name.chars().count() != 1 {
+        return Err(format!(
+            "expected a single module with a one character name, found `{}`",
+            predecessor
+        ));
+    }
+
+    Ok(ProductionSpec {
+        predecessor: predecessor.to_string(),
+        condition: None,
+        left_context: left.map(symbols),
+        right_context: right.map(symbols),
+        successor: SuccessorSpec::Template(String::new()),
+    })
+}
+
+// The symbols of a context, without their parameters or whitespace.
+fn symbols(context: &str) -> String {
+    let mut depth = 0;
+    context
+        .chars()
+        .filter(|c| {
+            match c {
+                '(' => depth += 1,
+                ')' => {
+                    depth -= 1;
+                    return false;
+                }
+                _ => {}
+            }
+            depth == 0 && !c.is_whitespace()
+        })
+        .collect()
+}
+
+// Rewrites Python's operators and literals as those of `Expr`. Python's `not`
+// binds looser than comparisons, so its operand is put in parentheses, up to
+// the next `and`, `or` or closing parenthesis.
+fn python_to_expr(text: &str) -> String {
+    let mut result = String::new();
+    let mut depth = 0;
+    let mut nots: Vec<usize> = vec![];
+    let close = |result: &mut String, nots: &mut Vec<usize>, depth: usize| {
+        while nots.last() == Some(&depth) {
+            nots.pop();
+            result.push(')');
+        }
+    };
+
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let word_len = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        if word_len > 1 {
+            let word = &rest[..word_len];
+            if word == "and" || word == "or" {
+                close(&mut result, &mut nots, depth);
+            }
+            match word {
+                "and" => result.push_str("&&"),
+                "or" => result.push_str("||"),
+                "not" => {
+                    result.push_str("!(");
+                    nots.push(depth);
+                    rest = rest[word_len..].trim_start();
+                    continue;
+                }
+                "True" => result.push('1'),
+                "False" => result.push('0'),
+                word => result.push_str(word),
+            }
+            rest = &rest[word_len..];
+            continue;
+        }
+
+        if rest.starts_with("**") {
+            result.push('^');
+            rest = &rest[2..];
+            continue;
+        }
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                close(&mut result, &mut nots, depth);
+                depth -= 1;
+            }
+            _ => {}
+        }
+        result.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+
+    close(&mut result, &mut nots, depth);
+    result.extend(nots.iter().map(|_| ')'));
+    result
+}
+
+// Rewrites the operators of `Expr` as Python's, only within parentheses
+// unless `whole`, since outside them they are module names. The operand of
+// `!` is put in parentheses, since Python's `not` binds looser.
+fn expr_to_python(text: &str, whole: bool) -> String {
+    let mut result = String::new();
+    let mut depth = 0;
+    let mut i = 0;
+    while let Some(c) = text[i..].chars().next() {
+        i += c.len_utf8();
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 && !whole {
+            result.push(c);
+            continue;
+        }
+
+        let rest = &text[i..];
+        match c {
+            '&' | '|' if rest.starts_with(c) => {
+                if !result.ends_with(' ') {
+                    result.push(' ');
+                }
+                result.push_str(if c == '&' { "and" } else { "or" });
+                if !rest[1..].starts_with(' ') {
+                    result.push(' ');
+                }
+                i += 1;
+            }
+            '!' if !rest.starts_with('=') => {
+                let len = operand_len(rest);
+                result += &format!("(not {})", expr_to_python(&rest[..len], true).trim());
+                i += len;
+            }
+            '^' => result.push_str("**"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+// The length of the operand at the start of `text` that a unary operator of
+// `Expr` applies to, including any exponent.
+fn operand_len(text: &str) -> usize {
+    let start = text.len() - text.trim_start().len();
+    let rest = &text[start..];
+    let mut len = match rest.chars().next() {
+        Some('!') | Some('-') => 1 + operand_len(&rest[1..]),
+        Some('(') => group_len(rest),
+        Some(_) => {
+            let len = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+                .unwrap_or(rest.len());
+            if rest[len..].starts_with('(') {
+                len + group_len(&rest[len..])
+            } else {
+                len
+            }
+        }
+        None => 0,
+    };
+
+    let after = &rest[len..];
+    let spaces = after.len() - after.trim_start().len();
+    if after.trim_start().starts_with('^') {
+        len += spaces + 1 + operand_len(&after[spaces + 1..]);
+    }
+    start + len
+}
+
+// The length of the parenthesised group at the start of `text`.
+fn group_len(text: &str) -> usize {
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+impl LpyModel {
+    /// Writes the model in L-Py's syntax. Specs with tables, stochastic
+    /// successors, a seed or a cut symbol other than `%` can't be written.
+    pub fn to_lpy(&self) -> Result<String, ExportError> {
+        let spec = &self.spec;
+        let unsupported = |field: &str, message: &str| ExportError {
+            field: field.into(),
+            message: message.into(),
+        };
+        if !spec.tables.is_empty() {
+            return Err(unsupported("tables", "L-Py has no production tables"));
+        }
+        if spec.cut != "%" {
+            return Err(unsupported("cut", "L-Py's cut symbol is always `%`"));
+        }
+        if spec.seed.is_some() {
+            return Err(unsupported("seed", "L-Py models can't be seeded"));
+        }
+
+        let mut lpy = String::new();
+        for (name, value) in &spec.constants {
+            lpy += &format!("{} = {:?}\n", name, value);
+        }
+        if !spec.constants.is_empty() {
+            lpy.push('\n');
+        }
+        lpy += &format!("Axiom: {}\n", expr_to_python(&spec.axiom, false));
+        lpy += &format!("derivation length: {}\n", self.derivation_length);
+        if !spec.ignore.is_empty() {
+            lpy += &format!("ignore: {}\n", spec.ignore);
+        }
+
+        let sections = [
+            ("production", &spec.productions),
+            ("decomposition", &spec.decomposition),
+            ("homomorphism", &spec.interpretation),
+        ];
+        for (name, productions) in sections.iter() {
+            if productions.is_empty() && *name != "production" {
+                continue;
+            }
+            lpy += &format!("\n{}:\n", name);
+            for (i, production) in productions.iter().enumerate() {
+                let field = format!("{}[{}]", name, i);
+                let successor = match &production.successor {
+                    SuccessorSpec::Template(t) => expr_to_python(t, false),
+                    SuccessorSpec::Weighted(_) => {
+                        return Err(unsupported(
+                            &field,
+                            "stochastic successors can't be written",
+                        ))
+                    }
+                };
+
+                let mut head = String::new();
+                if let Some(left) = &production.left_context {
+                    head += &format!("{} < ", left);
+                }
+                head += &production.predecessor;
+                if let Some(right) = &production.right_context {
+                    head += &format!(" > {}", right);
+                }
+
+                match &production.condition {
+                    Some(condition) => {
+                        lpy += &format!(
+                            "{} :\n    if {}:\n        produce {}\n",
+                            head,
+                            expr_to_python(condition, true),
+                            successor
+                        );
+                    }
+                    None => lpy += &format!("{} --> {}\n", head, successor),
+                }
+            }
+        }
+
+        lpy += "endlsystem\n";
+        Ok(lpy)
+    }
+
+    /// An executor drawing the model with `turtle` the way L-Py's turtle
+    /// does. `F` and `f` move forward by their parameter or 1, `+` and `-` turn
+    /// left and right by their parameter in degrees or 60, and `[` and `]` push
+    /// and pop the turtle. A [`BasicTurtle`] can't lift its pen, so `f` draws
+    /// like `F`.
+    pub fn executor<T, P>(&self, turtle: T) -> LSystemExecutor<T>
+    where
+        T: 'static + BasicTurtle<f64, f64, P>,
+        P: 'static,
+    {
+        let mut executor = LSystemExecutor::new(turtle);
+        let mut register = |token: &str, command: Command, default: f64| {
+            let command = Turtle::<P> {
+                command,
+                default,
+                phantom: PhantomData,
+            };
+            executor.register_rule(token.into(), command);
+        };
+        register("F", Command::Travel, 1.);
+        register("f", Command::Travel, 1.);
+        register("+", Command::Rotate, PI / 3.);
+        register("-", Command::RotateBack, PI / 3.);
+        register("[", Command::Push, 0.);
+        register("]", Command::Pop, 0.);
+        executor
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Command {
+    Travel,
+    Rotate,
+    RotateBack,
+    Push,
+    Pop,
+}
+
+// A turtle command whose parameter is optional. Angles are read in degrees.
+struct Turtle<P> {
+    command: Command,
+    default: f64,
+    phantom: PhantomData<P>,
+}
+
+impl<T: BasicTurtle<f64, f64, P>, P> CallParsed<T, Turtle<P>> for Turtle<P> {
+    fn call_parsed(&mut self, turtle: &mut T, params: &[Value]) -> Result<(), serde_json::Error> {
+        use serde::de::Error;
+
+        let value = match params {
+            [] => None,
+            [value] => Some(
+                value
+                    .as_f64()
+                    .ok_or_else(|| Error::custom("expected a number"))?,
+            ),
+            _ => return Err(Error::invalid_length(params.len(), &"at most 1 parameter")),
+        };
+        let angle = value.map_or(self.default, f64::to_radians);
+        match self.command {
+            Command::Travel => turtle.travel(value.unwrap_or(self.default)),
+            Command::Rotate => turtle.rotate(angle),
+            Command::RotateBack => turtle.rotate(-angle),
+            Command::Push => turtle.push(),
+            Command::Pop => turtle.pop(),
+        }
+        Ok(())
+    }
+}
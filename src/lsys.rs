@@ -0,0 +1,358 @@
+//! A plain text format for grammars, usually kept in `.lsys` files.
+//!
+//! ```text
+//! # Lines starting with `#` are comments.
+//! define len = 10
+//! angle: 25.7
+//! axiom: F(len)X
+//! ignore: +-
+//! seed: 7
+//!
+//! X -> F(len)[+X][-X]F(len)X : 0.7
+//! X -> F(len)[-X]F(len)X : 0.3
+//! F(x) : x > 1 -> F(x * 0.9)
+//!
+//! action F(x): travel(x)
+//! action +: rotate(angle)
+//! action -: rotate(-angle)
+//! action [: push
+//! action ]: pop
+//! ```
+//!
+//! Constants can be used in any later expression. The angle is given in degrees
+//! and defines the constant `angle` in radians. Productions are parametric, see
+//! [`Production::parametric`], and consecutive ones ending in `: weight` with
+//! the same predecessor and guard make up a stochastic production. Actions map
+//! a token to one of the turtle methods `travel`, `rotate`, `push`, `pop` and
+//! `flush`, with arguments computed from the parameters of the module.
+
+use crate::expr::{Expr, ParseError, Parser};
+use crate::parametric::{predecessor, Rule, Template};
+use crate::turtle::BasicTurtle;
+use crate::{check_weights, CallParsed, LSystem, LSystemExecutor, Production};
+use serde_json::Value;
+use std::f64::consts::PI;
+use std::fmt;
+use std::marker::PhantomData;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GrammarError {
+    pub message: String,
+    /// Counted from 1, as are columns, which count characters.
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for GrammarError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for GrammarError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Method {
+    Travel,
+    Rotate,
+    Push,
+    Pop,
+    Flush,
+}
+
+impl Method {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "travel" => Self::Travel,
+            "rotate" => Self::Rotate,
+            "push" => Self::Push,
+            "pop" => Self::Pop,
+            "flush" => Self::Flush,
+            _ => return None,
+        })
+    }
+
+    fn arity(self) -> usize {
+        match self {
+            Self::Travel | Self::Rotate => 1,
+            Self::Push | Self::Pop | Self::Flush => 0,
+        }
+    }
+}
+
+// Calls a turtle method with arguments computed from the module's parameters.
+struct Action<P> {
+    method: Method,
+    arity: usize,
+    args: Vec<Expr>,
+    constants: Vec<f64>,
+    phantom: PhantomData<P>,
+}
+
+impl<T, P> CallParsed<T, Action<P>> for Action<P>
+where
+    T: BasicTurtle<f64, f64, P>,
+    T::Error: fmt::Debug,
+{
+    fn call_parsed(&mut self, turtle: &mut T, params: &[Value]) -> Result<(), serde_json::Error> {
+        use serde::de::Error;
+
+        if params.len() != self.arity {
+            return Err(Error::invalid_length(
+                params.len(),
+                &format!("{} parameters", self.arity).as_str(),
+            ));
+        }
+        let mut values = params
+            .iter()
+            .map(|p| p.as_f64().ok_or_else(|| Error::custom("expected a number")))
+            .collect::<Result<Vec<_>, serde_json::Error>>()?;
+        values.extend(&self.constants);
+        let args: Vec<f64> = self.args.iter().map(|a| a.eval(&values)).collect();
+
+        match self.method {
+            Method::Travel => turtle.travel(args[0]),
+            Method::Rotate => turtle.rotate(args[0]),
+            Method::Push => turtle.push(),
+            Method::Pop => turtle.pop(),
+            Method::Flush => turtle
+                .flush()
+                .map_err(|e| Error::custom(format!("{:?}", e)))?,
+        }
+        Ok(())
+    }
+}
+
+// A production as read, before stochastic ones are grouped together.
+struct Parsed {
+    rule: Rule,
+    line: usize,
+}
+
+// An action as read, before the constants are all known.
+struct ParsedAction {
+    token: String,
+    method: Method,
+    arity: usize,
+    args: Vec<Expr>,
+}
+
+#[derive(Default)]
+struct Grammar {
+    names: Vec<String>,
+    values: Vec<f64>,
+    axiom: Option<Template>,
+    ignore: Option<String>,
+    seed: Option<u64>,
+    rules: Vec<Parsed>,
+    actions: Vec<ParsedAction>,
+}
+
+/// Parses a grammar, returning its system along with an executor that drives
+/// `turtle`.
+pub fn parse<T, P>(source: &str, turtle: T) -> Result<(LSystem, LSystemExecutor<T>), GrammarError>
+where
+    T: 'static + BasicTurtle<f64, f64, P>,
+    T::Error: fmt::Debug,
+    P: 'static,
+{
+    let mut grammar = read(source)?;
+    let system = grammar.system(source)?;
+
+    let mut executor = LSystemExecutor::new(turtle);
+    for action in grammar.actions {
+        executor.register_rule(
+            action.token,
+            Action::<P> {
+                method: action.method,
+                arity: action.arity,
+                args: action.args,
+                constants: grammar.values.clone(),
+                phantom: PhantomData,
+            },
+        );
+    }
+
+    Ok((system, executor))
+}
+
+/// Parses a grammar without building an executor for its actions.
+pub fn parse_system(source: &str) -> Result<LSystem, GrammarError> {
+    read(source)?.system(source)
+}
+
+fn read(source: &str) -> Result<Grammar, GrammarError> {
+    let mut grammar = Grammar::default();
+    for (i, line) in source.lines().enumerate() {
+        let error = |offset: usize, e: ParseError| GrammarError {
+            message: e.message,
+            line: i + 1,
+            column: line[..offset + e.position].chars().count() + 1,
+        };
+        let content = line.trim_start();
+        let offset = line.len() - content.len();
+        if content.is_empty() || content.starts_with('#') {
+            continue;
+        }
+        grammar.line(content, i + 1).map_err(|e| error(offset, e))?;
+    }
+    Ok(grammar)
+}
+
+impl Grammar {
+    // Builds the system, taking the productions.
+    fn system(&mut self, source: &str) -> Result<LSystem, GrammarError> {
+        let axiom = self.axiom.as_ref().ok_or_else(|| GrammarError {
+            message: "missing `axiom`".into(),
+            line: source.lines().count().max(1),
+            column: 1,
+        })?;
+        let mut system = LSystem::new(String::new());
+        system.axiom = axiom.expand(&self.values);
+        if let Some(ignore) = &self.ignore {
+            system.ignore = ignore.as_str().into();
+        }
+        if let Some(seed) = self.seed {
+            system.seed(seed);
+        }
+
+        let mut rules = std::mem::take(&mut self.rules).into_iter().peekable();
+        while let Some(Parsed { rule, line }) = rules.next() {
+            let mut group = vec![];
+            while let Some(next) = rules.next_if(|next| next.rule.continues(&rule)) {
+                group.push(next.rule);
+            }
+            let Rule {
+                token,
+                parameters,
+                condition,
+                weight,
+                modules,
+            } = rule;
+            let templates: Vec<(f64, Template)> = std::iter::once((weight, modules))
+                .chain(group.into_iter().map(|rule| (rule.weight, rule.modules)))
+                .map(|(weight, modules)| {
+                    let weight = weight.map_or(1., |w| w.eval(&self.values));
+                    (weight, Template::from_modules(modules))
+                })
+                .collect();
+            if let Err(e) = check_weights(templates.iter().map(|t| t.0)) {
+                return Err(GrammarError {
+                    message: e.to_string(),
+                    line,
+                    column: 1,
+                });
+            }
+
+            system.production_rules.push(Production::from_templates(
+                token,
+                parameters.len(),
+                condition,
+                templates,
+                self.values.clone(),
+            ));
+        }
+        Ok(system)
+    }
+
+    fn line(&mut self, line: &str, number: usize) -> Result<(), ParseError> {
+        let mut parser = Parser::new(line);
+        if let Some(rest) = line.strip_prefix("define ") {
+            parser.position = line.len() - rest.len();
+            let name = parser
+                .identifier()
+                .ok_or_else(|| parser.error("expected a constant name"))?;
+            parser.expect("=")?;
+            let value = self.expr(&mut parser)?;
+            self.define(name, value, &parser)?;
+        } else if let Some(rest) = line.strip_prefix("action ") {
+            parser.position = line.len() - rest.len();
+            self.action(&mut parser)?;
+        } else if line.contains("->") {
+            let rule = Rule::parse(line, &self.names, true)?;
+            self.rules.push(Parsed { rule, line: number });
+        } else if let Some(rest) = line.strip_prefix("axiom:") {
+            parser.position = line.len() - rest.len();
+            let axiom = Template::parse_modules(&mut parser, &self.names)?;
+            self.axiom = Some(axiom);
+        } else if let Some(rest) = line.strip_prefix("angle:") {
+            parser.position = line.len() - rest.len();
+            let degrees = self.expr(&mut parser)?;
+            self.define("angle", degrees * PI / 180., &parser)?;
+        } else if let Some(rest) = line.strip_prefix("ignore:") {
+            self.ignore = Some(rest.split_whitespace().collect());
+        } else if let Some(rest) = line.strip_prefix("seed:") {
+            parser.position = line.len() - rest.len();
+            parser.skip_whitespace();
+            let seed = parser.rest().trim_end().parse();
+            self.seed = Some(seed.map_err(|_| parser.error("expected an integer"))?);
+        } else {
+            return Err(parser.error("expected a declaration, production or action"));
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, name: &str, value: f64, parser: &Parser) -> Result<(), ParseError> {
+        if self.names.iter().any(|n| n == name) {
+            return Err(parser.error(format!("`{}` is already defined", name)));
+        }
+        self.names.push(name.to_string());
+        self.values.push(value);
+        Ok(())
+    }
+
+    // Evaluates an expression over the constants, which must end the line.
+    fn expr(&self, parser: &mut Parser) -> Result<f64, ParseError> {
+        let expr = parser.expr(&self.names)?;
+        parser.skip_whitespace();
+        if !parser.is_done() {
+            return Err(parser.error("expected an operator"));
+        }
+        Ok(expr.eval(&self.values))
+    }
+
+    fn action(&mut self, parser: &mut Parser) -> Result<(), ParseError> {
+        let (token, parameters) = predecessor(parser)?;
+        parser.expect(":")?;
+
+        parser.skip_whitespace();
+        let start = parser.position;
+        let method = parser
+            .identifier()
+            .and_then(Method::from_name)
+            .ok_or_else(|| ParseError {
+                message: "expected one of `travel`, `rotate`, `push`, `pop` or `flush`".into(),
+                position: start,
+            })?;
+
+        let variables: Vec<String> = parameters.iter().chain(&self.names).cloned().collect();
+        let mut args = vec![];
+        if parser.eat("(") && !parser.eat(")") {
+            loop {
+                args.push(parser.expr(&variables)?);
+                if parser.eat(")") {
+                    break;
+                }
+                parser.expect(",")?;
+            }
+        }
+        if args.len() != method.arity() {
+            return Err(ParseError {
+                message: format!("expected {} arguments", method.arity()),
+                position: start,
+            });
+        }
+        parser.skip_whitespace();
+        if !parser.is_done() {
+            return Err(parser.error("expected the end of the line"));
+        }
+
+        self.actions.push(ParsedAction {
+            token,
+            method,
+            arity: parameters.len(),
+            args,
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,31 @@
+//! What the [`lsystem!`](crate::lsystem) macro expands to.
+
+use crate::lsys;
+use crate::LSystem;
+
+// A statement of the macro, as the text of its tokens.
+#[doc(hidden)]
+pub enum Item {
+    Constant(&'static str, &'static str),
+    Axiom(&'static str),
+    Production(&'static str),
+}
+
+// Builds a system from the statements of the macro, by way of the `.lsys`
+// format. The macro has checked them already, so this doesn't fail.
+#[doc(hidden)]
+pub fn build(items: &[Item]) -> LSystem {
+    let lines: Vec<String> = items
+        .iter()
+        .map(|item| match item {
+            Item::Constant(name, expr) => format!("define {} = {}", name, expr),
+            Item::Axiom(axiom) => format!("axiom: {}", axiom),
+            Item::Production(rule) => rule.to_string(),
+        })
+        .collect();
+
+    lsys::parse_system(&lines.join("\n")).unwrap_or_else(|e| {
+        let statement = lines.get(e.line - 1).map_or("", String::as_str);
+        panic!("`lsystem!` let `{}` through: {}", statement, e.message)
+    })
+}
@@ -0,0 +1,140 @@
+//! Rewriting the chunks of a word in parallel, behind the `rayon` feature.
+
+use crate::history::Application;
+use crate::limits::{Guard, LimitError, Limits};
+use crate::word::{Module, Word};
+use crate::{LSystem, Pass};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rayon::prelude::*;
+
+impl LSystem {
+    /// Takes a step like [`LSystem::step`], rewriting chunks of `chunk_len`
+    /// modules on rayon's thread pool. Deterministic productions give the same
+    /// word as `step`. Stochastic ones draw from a stream of their chunk, seeded
+    /// from the system's rng once per pass, so the word only depends on the
+    /// seed and `chunk_len`, not on the number of threads. The `limits` aren't
+    /// checked, see [`LSystem::try_par_step`].
+    pub fn par_step(&mut self, chunk_len: usize) {
+        let table = self.table_for(self.generation);
+        self.par_step_with(table.as_deref(), chunk_len);
+    }
+
+    /// Takes a step with the named table in parallel, see [`LSystem::par_step`].
+    pub fn par_step_with(&mut self, table: Option<&str>, chunk_len: usize) {
+        let mut guard = Guard::new(Limits::default());
+        self.derive(table, |pass, word, rng, trace| {
+            pass.par_rewrite(word, chunk_len.max(1), rng.gen(), &mut guard, trace)
+        })
+        .expect("steps without limits can't fail");
+    }
+
+    pub fn par_step_by(&mut self, n: usize, chunk_len: usize) {
+        (0..n).for_each(|_| self.par_step(chunk_len))
+    }
+
+    /// Takes a step in parallel within the `limits`, see [`LSystem::try_step`].
+    /// Each chunk stops once it goes past them on its own, and the word is
+    /// checked again as the chunks are joined.
+    pub fn try_par_step(&mut self, chunk_len: usize) -> Result<(), LimitError> {
+        let table = self.table_for(self.generation);
+        self.try_par_step_with(table.as_deref(), chunk_len)
+    }
+
+    /// Takes a step with the named table in parallel within the `limits`, see
+    /// [`LSystem::try_par_step`].
+    pub fn try_par_step_with(
+        &mut self,
+        table: Option<&str>,
+        chunk_len: usize,
+    ) -> Result<(), LimitError> {
+        let rng = self.rng.clone();
+        let mut guard = Guard::new(self.limits);
+        let result = self.derive(table, |pass, word, rng, trace| {
+            pass.par_rewrite(word, chunk_len.max(1), rng.gen(), &mut guard, trace)
+        });
+        if result.is_err() {
+            self.rng = rng;
+        }
+        result
+    }
+
+    pub fn try_par_step_by(&mut self, n: usize, chunk_len: usize) -> Result<(), LimitError> {
+        (0..n).try_for_each(|_| self.try_par_step(chunk_len))
+    }
+}
+
+// The new modules of a chunk, where its rewriting stopped, whether it applied
+// any production and its applications.
+type Chunk = (Word, usize, bool, Option<Vec<Application>>);
+
+impl<'a> Pass<'a> {
+    fn par_rewrite(
+        &self,
+        word: &[Module],
+        chunk_len: usize,
+        seed: u64,
+        guard: &mut Guard,
+        trace: Option<&mut Vec<Application>>,
+    ) -> Result<(Word, bool), LimitError> {
+        let recording = trace.is_some();
+        let limits = guard.limits();
+        let rewrite_chunk = |k: usize, start: usize| -> Result<Chunk, LimitError> {
+            let mut rng = ChaCha8Rng::seed_from_u64(seed);
+            rng.set_stream(k as u64);
+            let mut applications = if recording { Some(vec![]) } else { None };
+            let end = ((k + 1) * chunk_len).min(word.len());
+            let (new_word, stop, rewritten) = self.rewrite_span(
+                word,
+                start,
+                end,
+                &mut rng,
+                &mut Guard::new(limits),
+                applications.as_mut(),
+            )?;
+            Ok((new_word, stop, rewritten, applications))
+        };
+
+        let chunks: Vec<Chunk> = (0..word.len().div_ceil(chunk_len))
+            .into_par_iter()
+            .map(|k| rewrite_chunk(k, k * chunk_len))
+            .collect::<Result<_, _>>()?;
+
+        // A match at the end of a chunk may run into the next ones, in which
+        // case those are rewritten again from where it ended.
+        guard.start_pass();
+        let mut new_word = Word(Vec::with_capacity(word.len()));
+        let mut rewritten_any = false;
+        let mut traced = vec![];
+        let mut stop = 0;
+        for (k, chunk) in chunks.into_iter().enumerate() {
+            let start = k * chunk_len;
+            if stop >= start + chunk_len {
+                continue;
+            }
+            let (modules, end, rewritten, applications) = if stop == start {
+                chunk
+            } else {
+                rewrite_chunk(k, stop)?
+            };
+
+            let offset = new_word.len();
+            for mut application in applications.into_iter().flatten() {
+                application.target.start += offset;
+                application.target.end += offset;
+                traced.push(application);
+            }
+            new_word.extend(modules.0);
+            guard.check(&new_word, &new_word[offset..])?;
+            rewritten_any |= rewritten;
+            stop = end;
+        }
+
+        guard.check_duration()?;
+
+        if let Some(trace) = trace {
+            *trace = traced;
+        }
+        Ok((new_word, rewritten_any))
+    }
+}
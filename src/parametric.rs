@@ -0,0 +1,109 @@
+use crate::expr::{Expr, ParseError, Parser};
+use crate::word::{number, Module, Symbol, Word};
+use crate::{Condition, Match, Production, Successor};
+use l_system_syntax::rule::modules;
+pub(crate) use l_system_syntax::rule::{predecessor, Rule};
+
+/// The successor of a parametric production, such as `A(x*0.5, y+1) F(x)`.
+/// Whitespace between modules is insignificant.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Template {
+    pub modules: Vec<(Symbol, Vec<Expr>)>,
+}
+
+impl Template {
+    pub fn parse(source: &str, variables: &[String]) -> Result<Self, ParseError> {
+        let mut parser = Parser::new(source);
+        let template = Self::parse_modules(&mut parser, variables)?;
+        if !parser.is_done() {
+            return Err(parser.error("expected a module"));
+        }
+        Ok(template)
+    }
+
+    pub(crate) fn parse_modules(
+        parser: &mut Parser,
+        variables: &[String],
+    ) -> Result<Self, ParseError> {
+        Ok(Self::from_modules(modules(parser, variables)?))
+    }
+
+    pub(crate) fn from_modules(modules: Vec<(char, Vec<Expr>)>) -> Self {
+        Self {
+            modules: modules
+                .into_iter()
+                .map(|(symbol, args)| (symbol.into(), args))
+                .collect(),
+        }
+    }
+
+    pub fn expand(&self, args: &[f64]) -> Word {
+        self.modules
+            .iter()
+            .map(|(symbol, exprs)| {
+                Module::new(
+                    *symbol,
+                    exprs.iter().map(|e| number(e.eval(args))).collect(),
+                )
+            })
+            .collect()
+    }
+}
+
+impl Production {
+    /// Parses a parametric production such as `A(x, y) : x > 1 -> A(x*0.5, y+1) F(x)`.
+    /// The guard after `:` is optional.
+    pub fn parametric(rule: &str) -> Result<Self, ParseError> {
+        let rule = Rule::parse(rule, &[], false)?;
+        Ok(Self::from_templates(
+            rule.token,
+            rule.parameters.len(),
+            rule.condition,
+            vec![(1., Template::from_modules(rule.modules))],
+            vec![],
+        ))
+    }
+
+    /// A parametric production with weighted successors, whose expressions take
+    /// the matched parameters followed by `constants`.
+    pub(crate) fn from_templates(
+        token: String,
+        arity: usize,
+        condition: Option<Expr>,
+        templates: Vec<(f64, Template)>,
+        constants: Vec<f64>,
+    ) -> Self {
+        let symbols = templates
+            .iter()
+            .map(|(_, template)| template.modules.iter().map(|m| m.0).collect())
+            .collect();
+        let successors = templates
+            .into_iter()
+            .map(|(weight, template)| {
+                let constants = constants.clone();
+                let successor = move |m: &Match| {
+                    if constants.is_empty() {
+                        template.expand(m.args)
+                    } else {
+                        template.expand(&[m.args, &constants].concat())
+                    }
+                };
+                (weight, Box::new(successor) as Successor)
+            })
+            .collect();
+
+        let mut production = Self::with_successors(token, successors);
+        production.arity = Some(arity);
+        production.successor_symbols = Some(symbols);
+        production.condition = condition.map(|c| {
+            Box::new(move |args: &[f64]| {
+                if constants.is_empty() {
+                    c.is_true(args)
+                } else {
+                    c.is_true(&[args, &constants].concat())
+                }
+            }) as Condition
+        });
+        production
+    }
+}
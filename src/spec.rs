@@ -0,0 +1,201 @@
+use crate::expr::{Expr, ParseError, Parser};
+use crate::parametric::{predecessor, Template};
+use crate::word::{Symbol, Word};
+use crate::{check_weights, LSystem, Production};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// A system described by data alone, with successors written as templates
+/// such as `F(x*0.5)[+A(x)]`. Unlike an [`LSystem`] it can be cloned, compared
+/// and stored in any serde format.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LSystemSpec {
+    /// A template over the constants.
+    pub axiom: String,
+    /// Named numbers every expression can use.
+    pub constants: BTreeMap<String, f64>,
+    pub productions: Vec<ProductionSpec>,
+    pub tables: BTreeMap<String, Vec<ProductionSpec>>,
+    pub decomposition: Vec<ProductionSpec>,
+    pub interpretation: Vec<ProductionSpec>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub ignore: String,
+    /// The cut symbol, `%` when left out. Empty for no cut symbol.
+    #[serde(skip_serializing_if = "is_default_cut")]
+    pub cut: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+impl Default for LSystemSpec {
+    fn default() -> Self {
+        Self {
+            axiom: String::new(),
+            constants: BTreeMap::new(),
+            productions: vec![],
+            tables: BTreeMap::new(),
+            decomposition: vec![],
+            interpretation: vec![],
+            ignore: String::new(),
+            cut: "%".into(),
+            seed: None,
+        }
+    }
+}
+
+fn is_default_cut(cut: &str) -> bool {
+    cut == "%"
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProductionSpec {
+    /// The token along with the names of its parameters, as in `A(x, y)`.
+    pub predecessor: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub condition: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub left_context: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub right_context: Option<String>,
+    pub successor: SuccessorSpec,
+}
+
+/// A single template, or weighted alternatives for a stochastic production.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SuccessorSpec {
+    Template(String),
+    Weighted(Vec<(f64, String)>),
+}
+
+/// A field of a spec that doesn't parse, named by its path such as
+/// `productions[2].condition`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpecError {
+    pub field: String,
+    pub error: ParseError,
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.error)
+    }
+}
+
+impl std::error::Error for SpecError {}
+
+impl LSystemSpec {
+    pub fn build(&self) -> Result<LSystem, SpecError> {
+        let names: Vec<String> = self.constants.keys().cloned().collect();
+        let values: Vec<f64> = self.constants.values().copied().collect();
+        let at = |field: String| move |error| SpecError { field, error };
+
+        let axiom = Template::parse(&self.axiom, &names).map_err(at("axiom".into()))?;
+        let mut system = LSystem::new(String::new());
+        system.axiom = axiom.expand(&values);
+        system.ignore = Word::from(self.ignore.as_str());
+        system.cut = Some(self.cut.as_str())
+            .filter(|cut| !cut.is_empty())
+            .map(Symbol::new);
+        if let Some(seed) = self.seed {
+            system.seed(seed);
+        }
+
+        let build_all = |field: &str, specs: &[ProductionSpec]| {
+            specs
+                .iter()
+                .enumerate()
+                .map(|(i, spec)| spec.build(&names, &values, &format!("{}[{}]", field, i)))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        system.production_rules = build_all("productions", &self.productions)?;
+        for (name, table) in &self.tables {
+            let table = build_all(&format!("tables.{}", name), table)?;
+            system.register_table(name.clone(), table);
+        }
+        system.decomposition_rules = build_all("decomposition", &self.decomposition)?;
+        system.interpretation_rules = build_all("interpretation", &self.interpretation)?;
+
+        Ok(system)
+    }
+}
+
+impl ProductionSpec {
+    pub(crate) fn build(
+        &self,
+        names: &[String],
+        values: &[f64],
+        field: &str,
+    ) -> Result<Production, SpecError> {
+        let at = |name: &str| {
+            let field = format!("{}.{}", field, name);
+            move |error| SpecError { field, error }
+        };
+
+        let mut parser = Parser::new(&self.predecessor);
+        let (token, parameters) = predecessor(&mut parser).map_err(at("predecessor"))?;
+        parser.skip_whitespace();
+        if !parser.is_done() {
+            return Err(at("predecessor")(
+                parser.error("expected the end of the predecessor"),
+            ));
+        }
+        let variables: Vec<String> = parameters.iter().chain(names).cloned().collect();
+
+        let condition = self
+            .condition
+            .as_ref()
+            .map(|c| Expr::parse(c, &variables))
+            .transpose()
+            .map_err(at("condition"))?;
+        let templates = match &self.successor {
+            SuccessorSpec::Template(t) => vec![(1., t)],
+            SuccessorSpec::Weighted(w) => w.iter().map(|(weight, t)| (*weight, t)).collect(),
+        };
+        if let Err(e) = check_weights(templates.iter().map(|t| t.0)) {
+            let field = match templates.iter().position(|(w, _)| w.is_nan() || *w < 0.) {
+                Some(k) => format!("successor[{}]", k),
+                None => "successor".into(),
+            };
+            return Err(at(&field)(ParseError {
+                message: e.to_string(),
+                position: 0,
+            }));
+        }
+        let templates = templates
+            .into_iter()
+            .map(|(weight, t)| Ok((weight, Template::parse(t, &variables)?)))
+            .collect::<Result<_, _>>()
+            .map_err(at("successor"))?;
+
+        let mut production = Production::from_templates(
+            token,
+            parameters.len(),
+            condition,
+            templates,
+            values.to_vec(),
+        );
+        production.left_context = self.left_context.as_deref().map(Word::from);
+        production.right_context = self.right_context.as_deref().map(Word::from);
+        Ok(production)
+    }
+}
+
+impl TryFrom<&LSystemSpec> for LSystem {
+    type Error = SpecError;
+
+    fn try_from(spec: &LSystemSpec) -> Result<Self, SpecError> {
+        spec.build()
+    }
+}
+
+impl TryFrom<LSystemSpec> for LSystem {
+    type Error = SpecError;
+
+    fn try_from(spec: LSystemSpec) -> Result<Self, SpecError> {
+        spec.build()
+    }
+}
@@ -0,0 +1,91 @@
+//! Detects derivations that reach a fixed point or a cycle, see
+//! [`LSystem::step_until_stable`].
+
+use crate::limits::LimitError;
+use crate::word::Module;
+use crate::LSystem;
+use serde_json::Value;
+use std::collections::hash_map::{DefaultHasher, HashMap};
+use std::hash::{Hash, Hasher};
+
+/// How a derivation settled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stability {
+    /// The word stopped changing from this generation on.
+    FixedPoint(usize),
+    /// The word repeats every `period` generations from `start` on.
+    Cycle { start: usize, period: usize },
+    /// No generation repeated within the steps allowed.
+    Unstable,
+}
+
+// The base of the polynomial hash, an odd constant with well mixed bits.
+const BASE: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// A rolling hash of a word's symbols and parameters, updated a module at a
+/// time. Ages aren't included.
+pub fn word_hash(word: &[Module]) -> u64 {
+    word.iter().fold(0, |hash, module| {
+        hash.wrapping_mul(BASE).wrapping_add(module_hash(module))
+    })
+}
+
+fn module_hash(module: &Module) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    module.symbol.hash(&mut hasher);
+    module
+        .params
+        .iter()
+        .for_each(|p| hash_value(p, &mut hasher));
+    hasher.finish()
+}
+
+fn hash_value(value: &Value, hasher: &mut impl Hasher) {
+    match value {
+        Value::Null => 0.hash(hasher),
+        Value::Bool(b) => (1, b).hash(hasher),
+        Value::Number(n) => (2, n.as_f64().map(f64::to_bits)).hash(hasher),
+        Value::String(s) => (3, s).hash(hasher),
+        Value::Array(values) => {
+            (4, values.len()).hash(hasher);
+            values.iter().for_each(|v| hash_value(v, hasher));
+        }
+        Value::Object(map) => {
+            (5, map.len()).hash(hasher);
+            for (key, v) in map {
+                key.hash(hasher);
+                hash_value(v, hasher);
+            }
+        }
+    }
+}
+
+impl LSystem {
+    /// Steps until a generation repeats an earlier one, up to `max_steps` times,
+    /// leaving the system at the first repeat. Generations with the same
+    /// [`word_hash`] and length are compared module by module, so every word
+    /// along the way is kept. A repeat only means the derivation stays in a
+    /// cycle when the productions are deterministic. Steps are taken with
+    /// [`LSystem::try_step`], so they fail if they go past the `limits`.
+    pub fn step_until_stable(&mut self, max_steps: usize) -> Result<Stability, LimitError> {
+        let first = self.generation;
+        let mut words = vec![self.axiom.clone()];
+        let mut seen: HashMap<(u64, usize), Vec<usize>> = HashMap::new();
+        seen.insert((word_hash(&self.axiom), self.axiom.len()), vec![first]);
+
+        for _ in 0..max_steps {
+            self.try_step()?;
+            let key = (word_hash(&self.axiom), self.axiom.len());
+            let same = seen.entry(key).or_default();
+            if let Some(&start) = same.iter().find(|g| words[**g - first] == self.axiom) {
+                return Ok(match self.generation - start {
+                    1 => Stability::FixedPoint(start),
+                    period => Stability::Cycle { start, period },
+                });
+            }
+            same.push(self.generation);
+            words.push(self.axiom.clone());
+        }
+        Ok(Stability::Unstable)
+    }
+}
@@ -1,4 +1,6 @@
-use crate::*;
+use crate::{word::*, *};
+use proptest::prelude::*;
+use std::convert::TryFrom;
 
 #[test]
 // This test taken from http://www.paulbourke.net/fractals/lsys/
@@ -67,3 +69,971 @@ fn test_non_primitive_executor() {
 
     assert_eq!(executor.state, 17);
 }
+
+#[test]
+// Signal propagation from The Algorithmic Beauty of Plants, section 1.8
+fn test_context_sensitive_production() {
+    let mut system = LSystem::new("baaaaaaa".into());
+    system.register_context_rule(Some("b".into()), "a".into(), None, || "b".into());
+    system.register_rule("b".into(), || "a".into());
+
+    system.step();
+    assert_eq!(system.axiom, "abaaaaaa".to_owned());
+
+    system.step_by(3);
+    assert_eq!(system.axiom, "aaaabaaa".to_owned());
+}
+
+#[test]
+fn test_bracketed_context() {
+    let mut system = LSystem::new("A[+B]C".into());
+    system.ignore = "+".into();
+    system.register_context_rule(Some("A".into()), "B".into(), None, || "Y".into());
+    system.register_context_rule(None, "A".into(), Some("[+B]C".into()), || "Z".into());
+    system.register_context_rule(Some("A".into()), "C".into(), None, || "X".into());
+    system.register_context_rule(None, "B".into(), Some("C".into()), || "W".into());
+
+    system.step();
+    assert_eq!(system.axiom, "Z[+Y]X".to_owned());
+}
+
+#[test]
+fn test_stochastic_production() {
+    let derive = |seed| {
+        let mut system = LSystem::new("FFFFFFFFFFFFFFFF".into());
+        system.seed(seed);
+        system
+            .register_stochastic_rule(
+                "F".into(),
+                vec![(1., "A".into()), (2., "B".into()), (0., "C".into())],
+            )
+            .unwrap();
+        system.step();
+        system.axiom.to_string()
+    };
+
+    let axiom = derive(7);
+    assert_eq!(axiom, derive(7));
+    assert_eq!(axiom.len(), 16);
+    assert!(axiom.contains('A') && axiom.contains('B') && !axiom.contains('C'));
+    assert_ne!(axiom, derive(8));
+
+    let mut system = LSystem::new("AXA".into());
+    let zero = vec![(0., "B".into()), (0., "C".into())];
+    assert_eq!(
+        system.register_stochastic_rule("A".into(), zero),
+        Err(WeightError::ZeroTotal)
+    );
+    let negative = vec![(1., "B".into()), (-1., "C".into())];
+    assert_eq!(
+        system.register_stochastic_rule("A".into(), negative),
+        Err(WeightError::Invalid(-1.))
+    );
+    assert!(matches!(
+        Production::stochastic("A".into(), vec![(f64::NAN, "B".into())]),
+        Err(WeightError::Invalid(w)) if w.is_nan()
+    ));
+    assert!(system.production_rules.is_empty());
+}
+
+#[test]
+fn test_parametric_production() {
+    let mut system = LSystem::new("A(4, 0)B(3)".into());
+    system
+        .register_parametric_rule("A(x, y) : x > 1 -> A(x*0.5, y+1) F(x)")
+        .unwrap();
+    system
+        .register_parametric_rule("B(x) -> B(max(x - 1, 0)) [+F(x^2)]")
+        .unwrap();
+
+    system.step();
+    assert_eq!(system.axiom, "A(2,1)F(4)B(2)[+F(9)]".to_owned());
+
+    system.step_by(2);
+    assert_eq!(
+        system.axiom,
+        "A(1,2)F(2)F(4)B(0)[+F(1)][+F(4)][+F(9)]".to_owned()
+    );
+
+    let mut executor = LSystemExecutor::new(0.);
+    executor.register_rule("F".into(), |state: &mut f64, d: f64| *state += d);
+    executor.execute(&system).unwrap();
+    assert_eq!(executor.state, 20.);
+}
+
+#[test]
+fn test_parametric_parse_errors() {
+    let error = Production::parametric("A(x) : y > 1 -> B").err().unwrap();
+    assert_eq!(error.message, "unknown variable `y`");
+    assert_eq!(error.position, 7);
+
+    assert!(Production::parametric("A(x) -> B(x").is_err());
+    assert!(Production::parametric("A(x) B").is_err());
+}
+
+#[test]
+fn test_longest_match_wins() {
+    for reversed in [false, true] {
+        let mut rules = vec![("F", "a"), ("FF", "b"), ("FFF", "c")];
+        if reversed {
+            rules.reverse();
+        }
+
+        let mut system = LSystem::new("FFFFFF+FF".into());
+        for (token, successor) in rules {
+            system.register_rule(token.into(), move || successor.into());
+        }
+        system.step();
+        assert_eq!(system.axiom, "cc+b".to_owned());
+    }
+}
+
+fn reference_step(axiom: &str, rules: &[(String, String)]) -> String {
+    if axiom.is_empty() {
+        return String::new();
+    }
+
+    let mut best: Option<&(String, String)> = None;
+    for rule in rules {
+        if axiom.starts_with(&rule.0) && best.is_none_or(|b| rule.0.len() > b.0.len()) {
+            best = Some(rule);
+        }
+    }
+
+    match best {
+        Some((token, successor)) => {
+            successor.clone() + &reference_step(&axiom[token.len()..], rules)
+        }
+        None => axiom[..1].to_string() + &reference_step(&axiom[1..], rules),
+    }
+}
+
+proptest! {
+    #[test]
+    fn test_step_matches_reference(
+        axiom in "[ABC+]{0,24}",
+        rules in prop::collection::vec(("[ABC]{1,3}", "[ABC+]{0,4}"), 0..6),
+    ) {
+        let mut system = LSystem::new(axiom.clone());
+        for (token, successor) in rules.clone() {
+            system.register_rule(token, move || successor.clone());
+        }
+        system.step();
+        prop_assert_eq!(system.axiom, reference_step(&axiom, &rules));
+    }
+
+    #[test]
+    fn test_step_is_order_independent(
+        axiom in "[ABC]{0,24}",
+        rules in prop::collection::btree_map("[ABC]{1,3}", "[ABC]{0,4}", 0..6),
+    ) {
+        let derive = |rules: Vec<(String, String)>| {
+            let mut system = LSystem::new(axiom.clone());
+            for (token, successor) in rules {
+                system.register_rule(token, move || successor.clone());
+            }
+            system.step_by(2);
+            system.axiom
+        };
+
+        let rules: Vec<_> = rules.into_iter().collect();
+        prop_assert_eq!(derive(rules.clone()), derive(rules.into_iter().rev().collect()));
+    }
+}
+
+#[test]
+fn test_word_round_trip() {
+    let text = r#"F(1,2.5)+[A("a)b",[1,{"c":2}])]B(x"#;
+    let word = Word::from(text);
+
+    assert_eq!(word.len(), 8);
+    assert_eq!(word[0].numeric_params(), Some(vec![1., 2.5]));
+    assert_eq!(word[3].params[0], serde_json::json!("a)b"));
+    assert_eq!(word[6].symbol, Symbol::from('('));
+    assert_eq!(word.to_string(), text);
+
+    let apex = Module::new("Apex", vec![word::number(2.)]);
+    assert_eq!(Symbol::new("Apex"), apex.symbol);
+    let apex = Word::from(vec![apex]);
+    assert_eq!(word::match_token(&apex, "Apex"), Some(1));
+    assert_eq!(word::match_token(&word, "F+"), None);
+    assert_eq!(apex.to_string(), "Apex(2)");
+}
+
+#[test]
+fn test_match_rule() {
+    let mut system = LSystem::new("XA(1)YA(2)".into());
+    system.register_match_rule("A".into(), |m: &Match| {
+        let n = m.params()[0].as_i64().unwrap();
+        let left = m.neighbour(-1).unwrap();
+        assert_eq!(m.neighbour(1), m.right().first());
+        format!("A({})[{}{}{}]", n + 1, left, m.index, m.generation)
+    });
+
+    system.step();
+    assert_eq!(system.axiom, "XA(2)[X10]YA(3)[Y30]".to_owned());
+
+    system.step();
+    assert_eq!(system.axiom, "XA(3)[X11][X10]YA(4)[Y81][Y30]".to_owned());
+    assert_eq!(system.generation, 2);
+}
+
+#[test]
+fn test_expansion_matches_step() {
+    let mut system = LSystem::new("A(4, 0)FB(3)".into());
+    system
+        .register_parametric_rule("A(x, y) : x > 1 -> A(x*0.5, y+1) [F(x)]")
+        .unwrap();
+    system
+        .register_parametric_rule("B(x) -> B(x+1) A(x, 0)")
+        .unwrap();
+    system.register_rule("F".into(), || "FF".into());
+
+    let expanded: Word = system.expand(4).unwrap().collect();
+    assert_eq!(system.generation, 0);
+
+    system.step_by(4);
+    assert_eq!(expanded, system.axiom);
+
+    // The cut drops the rest of its branch at every level, as in `step`.
+    let mut system = LSystem::new("A[B%[A]C]%A".into());
+    system.register_rule("A".into(), || "F[A%F]A".into());
+    assert_eq!(system.growth_matrix(), Err(analysis::AnalysisError::Cut));
+    let expanded: Word = system.expand(3).unwrap().collect();
+    system.step_by(3);
+    assert_eq!(expanded, system.axiom);
+}
+
+#[test]
+fn test_execute_expansion() {
+    let mut system = LSystem::new("A".into());
+    system.register_rule("A".into(), || "AB".into());
+    system.register_rule("B".into(), || "A".into());
+
+    let mut executor = LSystemExecutor::new((0, 0));
+    executor.register_rule("A".into(), |state: &mut (u64, u64)| state.0 += 1);
+    executor.register_rule("BA".into(), |state: &mut (u64, u64)| state.1 += 1);
+    executor.execute_iter(system.expand(25).unwrap()).unwrap();
+    assert_eq!(executor.state, (46369, 75024));
+
+    let mut reference = LSystemExecutor::new((0, 0));
+    reference.register_rule("A".into(), |state: &mut (u64, u64)| state.0 += 1);
+    reference.register_rule("BA".into(), |state: &mut (u64, u64)| state.1 += 1);
+    system.step_by(25);
+    reference.execute(&system).unwrap();
+    assert_eq!(reference.state, executor.state);
+}
+
+#[test]
+fn test_growth_matrix() {
+    let mut system = LSystem::new("A".into());
+    system.register_rule("A".into(), || "AB".into());
+    system.register_rule("B".into(), || "A".into());
+
+    let matrix = system.growth_matrix().unwrap();
+    assert_eq!(matrix.growth(), analysis::Growth::Exponential);
+    assert_eq!(
+        matrix.length(100).to_string(),
+        "927372692193078999176".to_owned()
+    );
+
+    system.step_by(10);
+    let counts = matrix.symbol_counts(10);
+    assert_eq!(counts[&Symbol::from('A')], 89u32.into());
+    assert_eq!(counts[&Symbol::from('B')], 55u32.into());
+    assert_eq!(matrix.length(10), system.axiom.len().into());
+
+    let mut system = LSystem::new("A[+A]".into());
+    system.register_rule("A".into(), || "AB".into());
+    system.register_rule("B".into(), || "BC".into());
+    system.register_rule("C".into(), || "".into());
+    assert_eq!(
+        system.growth_matrix().unwrap().growth(),
+        analysis::Growth::Polynomial(1)
+    );
+    system.register_rule("C".into(), || "C".into());
+    system.production_rules.swap(2, 3);
+    assert_eq!(
+        system.growth_matrix().unwrap().growth(),
+        analysis::Growth::Polynomial(2)
+    );
+
+    system.register_context_rule(Some("A".into()), "B".into(), None, || "".into());
+    assert_eq!(
+        system.growth_matrix(),
+        Err(analysis::AnalysisError::ContextSensitive("B".into()))
+    );
+
+    // Decomposition rules and tables change the word in ways the analysis
+    // doesn't see.
+    let mut system = LSystem::new("A".into());
+    system.register_rule("A".into(), || "AB".into());
+    system.register_decomposition_rule(Production::new("B".into(), || "CCC".into()));
+    assert_eq!(
+        system.growth_matrix(),
+        Err(analysis::AnalysisError::Decomposition)
+    );
+    assert!(system.random_access(3).is_err());
+    assert!(system.expand(3).is_err());
+    system.decomposition_rules.clear();
+    system.table = Some("other".into());
+    assert_eq!(system.growth_matrix(), Err(analysis::AnalysisError::Tables));
+}
+
+#[test]
+fn test_random_access() {
+    let mut system = LSystem::new("FX".into());
+    system.register_rule("X".into(), || "X+YF+".into());
+    system.register_rule("Y".into(), || "-FX-Y".into());
+
+    let access = system.random_access(8).unwrap();
+    system.step_by(8);
+    let word = &system.axiom;
+    assert_eq!(access.len(), word.len() as u128);
+    for i in (0..word.len()).step_by(7) {
+        assert_eq!(access.get(i as u128).as_ref(), Some(&word[i]));
+    }
+    assert_eq!(access.get(word.len() as u128), None);
+    assert_eq!(access.slice(100..140), Word::from(word[100..140].to_vec()));
+    assert_eq!(access.slice(0..u128::MAX), *word);
+
+    let mut system = LSystem::new("A".into());
+    system.register_rule("A".into(), || "AB".into());
+    system.register_rule("B".into(), || "A".into());
+    let access = system.random_access(150).unwrap();
+    assert!(access.len() > u64::MAX as u128);
+    assert_eq!(access.slice(0..5), "ABAAB");
+    assert_eq!(
+        access.get(access.len() - 1),
+        Some(Module::from(Symbol::from('A')))
+    );
+}
+
+#[test]
+fn test_production_tables() {
+    let mut system = LSystem::new("A".into());
+    system.register_rule("A".into(), || "IA".into());
+    system.register_table(
+        "flowering".into(),
+        vec![Production::new("A".into(), || "K".into())],
+    );
+
+    system.step_with(Some("flowering"));
+    assert_eq!(system.axiom, "K");
+
+    system.axiom = "A".into();
+    system.generation = 0;
+    system.set_schedule(|generation| {
+        if generation < 3 {
+            None
+        } else {
+            Some("flowering".into())
+        }
+    });
+    let expanded: Word = system.expand(5).unwrap().collect();
+    system.step_by(5);
+    assert_eq!(system.axiom, "IIIK");
+    assert_eq!(expanded, system.axiom);
+
+    system.table = Some("flowering".into());
+    system.schedule = None;
+    system.axiom = "A".into();
+    system.step();
+    assert_eq!(system.axiom, "K");
+}
+
+#[test]
+fn test_timed_system() {
+    let mut system = LSystem::new("A".into());
+    system.register_rule("A".into(), || "BA".into());
+    system.terminal_ages.insert(Symbol::from('A'), 1.);
+
+    system.advance(0.5).unwrap();
+    assert_eq!(system.axiom, "A");
+    system.advance(0.75).unwrap();
+    assert_eq!(system.axiom, "BA");
+    system.advance(2.).unwrap();
+    assert_eq!(system.axiom, "BBBA");
+
+    let ages: Vec<f64> = system.axiom.iter().map(|m| m.age).collect();
+    for (age, expected) in ages.iter().zip(&[2.25, 1.25, 0.25, 0.25]) {
+        assert!((age - expected).abs() < 1e-9, "{:?}", ages);
+    }
+    assert_eq!(system.time, 3.25);
+    assert_eq!(system.generation, 0);
+
+    system.terminal_ages.insert(Symbol::from('B'), 0.);
+    let error = system.advance(1.).unwrap_err();
+    assert_eq!((error.symbol, error.age), (Symbol::from('B'), 0.));
+    assert_eq!(system.time, 3.25);
+
+    let mut aged = Word::from("AB");
+    aged[0].age = 2.;
+    assert_eq!(aged, "AB");
+}
+
+#[test]
+fn test_query_module() {
+    let mut system = LSystem::new("A".into());
+    system.register_parametric_rule("A -> F?P(0)A").unwrap();
+    system
+        .register_parametric_rule("?P(x) : x >= 3 -> X")
+        .unwrap();
+
+    for _ in 0..3 {
+        system.step();
+        let mut executor = LSystemExecutor::new(0.);
+        executor.register_rule("F".into(), |x: &mut f64| *x += 1.);
+        executor.register_query_rule("?P".into(), |x: &f64| vec![number(*x)]);
+        executor.execute_mut(&mut system).unwrap();
+    }
+    assert_eq!(system.axiom, "F?P(1)F?P(2)F?P(3)A");
+
+    system.step();
+    assert_eq!(system.axiom, "F?P(1)F?P(2)FXF?P(0)A");
+}
+
+#[test]
+fn test_decomposition_and_interpretation() {
+    let mut system = LSystem::new("A".into());
+    system.register_rule("A".into(), || "AB".into());
+    system.register_decomposition_rule(Production::new("B".into(), || "L[C]".into()));
+    system.register_decomposition_rule(Production::new("C".into(), || "L".into()));
+    system.step();
+    assert_eq!(system.axiom, "AL[C]");
+    system.decomposition_depth = 2;
+    system.step();
+    assert_eq!(system.axiom, "AL[L]L[L]");
+
+    system.register_interpretation_rule(Production::new("L".into(), || "FK".into()));
+    system.register_interpretation_rule(Production::new("K".into(), || "F".into()));
+    let count = |system: &LSystem| {
+        let mut executor = LSystemExecutor::new(0);
+        executor.register_rule("F".into(), |count: &mut i32| *count += 1);
+        executor.execute(system).unwrap();
+        executor.state
+    };
+    assert_eq!(count(&system), 4);
+    system.interpretation_depth = 2;
+    assert_eq!(count(&system), 8);
+    assert_eq!(system.axiom, "AL[L]L[L]");
+}
+
+#[test]
+fn test_cut_symbol() {
+    let mut system = LSystem::new("F[FA[FB]F]FA".into());
+    system.register_rule("A".into(), || "%".into());
+    system.step();
+    assert_eq!(system.axiom, "F[F%[FB]F]F%");
+    system.step();
+    assert_eq!(system.axiom, "F[F]F");
+
+    system.axiom = "F[A]X".into();
+    system.cut = Some(Symbol::from('X'));
+    system.step();
+    assert_eq!(system.axiom, "F[%]");
+}
+
+#[derive(Default)]
+struct RecordingTurtle {
+    position: (f64, f64),
+    heading: f64,
+    stack: Vec<((f64, f64), f64)>,
+}
+
+impl turtle::BasicTurtle<f64, f64, (f64, f64)> for RecordingTurtle {
+    type Error = ();
+
+    fn heading(&self) -> &f64 {
+        &self.heading
+    }
+
+    fn rotate(&mut self, radians: f64) {
+        self.heading += radians;
+    }
+
+    fn position(&self) -> &(f64, f64) {
+        &self.position
+    }
+
+    fn travel(&mut self, distance: f64) {
+        self.position.0 += distance * self.heading.cos();
+        self.position.1 += distance * self.heading.sin();
+    }
+
+    fn push(&mut self) {
+        self.stack.push((self.position, self.heading));
+    }
+
+    fn pop(&mut self) {
+        let (position, heading) = self.stack.pop().unwrap();
+        self.position = position;
+        self.heading = heading;
+    }
+
+    fn flush(&mut self) -> Result<(), ()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_lsys_grammar() {
+    let source = "
+        # a square corner
+        define len = 2
+        angle: 90
+        axiom: F(len)+F(len)A
+
+        F(x) : x > 1 -> F(x / 2) F(x / 2)
+        A -> [B] : len - 1
+        A -> C : 0
+
+        action F(x): travel(x)
+        action +: rotate(angle)
+        action [: push
+        action ]: pop
+        action B: travel(100)
+    ";
+    let (mut system, mut executor) = lsys::parse(source, RecordingTurtle::default()).unwrap();
+    system.step();
+    assert_eq!(system.axiom, "F(1)F(1)+F(1)F(1)[B]");
+
+    executor.execute(&system).unwrap();
+    let (x, y) = executor.state.position;
+    assert!((x - 2.).abs() < 1e-9 && (y - 2.).abs() < 1e-9);
+    assert!(executor.state.stack.is_empty());
+
+    let error = |source: &str| {
+        lsys::parse(source, RecordingTurtle::default())
+            .err()
+            .unwrap()
+    };
+    let e = error("axiom: X\naction X: fly");
+    assert_eq!((e.line, e.column), (2, 11));
+    let e = error("axiom: X\n\n  X -> F(y)");
+    assert_eq!(
+        (e.line, e.column, e.message.as_str()),
+        (3, 10, "unknown variable `y`")
+    );
+    let e = error("define a = 1\ndefine a = 2\naxiom: X");
+    assert_eq!(e.to_string(), "2:13: `a` is already defined");
+    assert_eq!(error("X -> Y").message, "missing `axiom`");
+    let e = error("axiom: X\nX -> F : 1 +");
+    assert_eq!(
+        (e.line, e.column, e.message.as_str()),
+        (2, 13, "expected an expression")
+    );
+}
+
+#[test]
+fn test_spec_round_trip() {
+    let json = r#"{
+        "axiom": "A(len)",
+        "constants": { "len": 4 },
+        "productions": [
+            { "predecessor": "A(x)", "condition": "x > 1", "successor": "F(x)[+A(x/2)]" },
+            { "predecessor": "F(x)", "successor": [[1, "F(x)"], [0, "G(x)"]] },
+            { "predecessor": "B", "left_context": "F", "successor": "C" }
+        ],
+        "seed": 3
+    }"#;
+    let spec: spec::LSystemSpec = serde_json::from_str(json).unwrap();
+    let round_trip = serde_json::from_str(&serde_json::to_string(&spec).unwrap()).unwrap();
+    assert_eq!(spec, round_trip);
+
+    let mut system = LSystem::try_from(&spec).unwrap();
+    system.step_by(2);
+    assert_eq!(system.axiom, "F(4)[+F(2)[+A(1)]]");
+
+    let mut spec = spec;
+    spec.productions[1].condition = Some("y > 1".into());
+    let error = spec.build().err().unwrap();
+    assert_eq!(error.field, "productions[1].condition");
+    assert_eq!(error.error.message, "unknown variable `y`");
+    spec.productions[1].condition = None;
+    spec.productions[1].successor =
+        spec::SuccessorSpec::Weighted(vec![(1., "F".into()), (f64::NAN, "G".into())]);
+    let error = spec.build().err().unwrap();
+    assert_eq!(error.field, "productions[1].successor[1]");
+
+    // Formats without null, like TOML, can store every spec.
+    let mut spec: spec::LSystemSpec = serde_json::from_str(json).unwrap();
+    let toml = toml::to_string(&spec).unwrap();
+    assert_eq!(toml::from_str::<spec::LSystemSpec>(&toml).unwrap(), spec);
+    spec.cut = String::new();
+    assert_eq!(LSystem::try_from(&spec).unwrap().cut, None);
+    let toml = toml::to_string(&spec).unwrap();
+    assert_eq!(toml::from_str::<spec::LSystemSpec>(&toml).unwrap(), spec);
+}
+
+#[test]
+fn test_fractint_import() {
+    let source = "
+; two systems
+Koch {      ; the snowflake
+  Angle 6
+  Axiom F--F--F
+  f=F+F--F+F
+}
+
+Steps { Angle 4
+  Axiom F[\\90@.5F]!+F|F }
+";
+    let mut systems = fractint::parse(source).unwrap();
+    assert_eq!(systems.len(), 2);
+    assert_eq!(systems[0].name, "Koch");
+    systems[0].system.step();
+    assert_eq!(systems[0].system.axiom, "F+F--F+F--F+F--F+F--F+F--F+F");
+
+    let steps = &systems[1];
+    assert_eq!(steps.system.axiom, "F[\\(90)@(0.5)F]!+F|F");
+    let mut executor = steps.executor(RecordingTurtle::default());
+    executor.execute(&steps.system).unwrap();
+    let (x, y) = executor.state.turtle.position;
+    assert!((x - 1.).abs() < 1e-9 && y.abs() < 1e-9, "{:?}", (x, y));
+
+    let error = fractint::parse("Bad {\n  Axiom F\n  F+F\n}").err().unwrap();
+    assert_eq!((error.line, error.column), (3, 3));
+    let error = fractint::parse("Bad {\n  Axiom F@\n}").err().unwrap();
+    assert_eq!((error.line, error.column), (2, 11));
+}
+
+#[test]
+fn test_lpy_round_trip() {
+    let source = "
+# constants come before the rules
+scale = 0.5
+
+Axiom: A(4) B C(2) D B C(3) D
+derivation length: 2
+
+production:
+A(x) --> F(x) [+(30) A(x * scale)]
+B < C(x) > D :
+    if x > 1 and not x == 3:
+        produce C(x ** 2)
+    produce E
+
+homomorphism:
+E --> F(2)
+endlsystem
+";
+    let model = lpy::parse(source).unwrap();
+    assert_eq!(model.derivation_length, 2);
+    assert_eq!(model.spec.productions.len(), 3);
+    assert_eq!(model.spec.interpretation.len(), 1);
+
+    let derive = |model: &lpy::LpyModel| {
+        let mut system = model.spec.build().unwrap();
+        system.step_by(model.derivation_length);
+        system.axiom.to_string()
+    };
+    assert_eq!(derive(&model), "F(4)[+(30)F(2)[+(30)A(1)]]BC(16)DBED");
+
+    let exported = model.to_lpy().unwrap();
+    let imported = lpy::parse(&exported).unwrap();
+    assert_eq!(imported.spec.constants, model.spec.constants);
+    assert_eq!(derive(&imported), derive(&model));
+
+    let mut system = model.spec.build().unwrap();
+    system.step_by(model.derivation_length);
+    let mut executor = model.executor(RecordingTurtle::default());
+    executor.execute(&system).unwrap();
+    assert_eq!(executor.state.position, (6., 0.));
+
+    let error = lpy::parse("Axiom: A\nproduction:\nAB --> A").err().unwrap();
+    assert_eq!((error.line, error.column), (3, 1));
+    let error = lpy::parse("Axiom: A\nhomomorphism:\nA --> B\nB --> C(y)")
+        .err()
+        .unwrap();
+    assert!(error.message.starts_with("interpretation[1].successor:"));
+
+    let mut model = lpy::parse("AXIOM: A(1)\nIgnore: + -").unwrap();
+    assert_eq!(model.spec.axiom, "A(1)");
+    assert_eq!(model.spec.ignore, "+-");
+    let exported = model.to_lpy().unwrap();
+    assert_eq!(lpy::parse(&exported).unwrap().spec.ignore, "+-");
+    model.spec.seed = Some(1);
+    assert_eq!(model.to_lpy().err().unwrap().field, "seed");
+}
+
+#[test]
+fn test_history() {
+    for recording in [history::Recording::Words, history::Recording::Diffs] {
+        let mut system = LSystem::new("A".into());
+        system.register_rule("A".into(), || "AB".into());
+        system
+            .register_stochastic_rule("B".into(), vec![(1., "A".into()), (1., "AC".into())])
+            .unwrap();
+        system
+            .decomposition_rules
+            .push(Production::new("C".into(), String::new));
+        system.seed(3);
+        system.record(recording);
+
+        let mut words = vec![system.axiom.clone()];
+        for _ in 0..5 {
+            system.step();
+            words.push(system.axiom.clone());
+        }
+        let history = system.history.as_ref().unwrap();
+        for (generation, word) in words.iter().enumerate() {
+            assert_eq!(history.word(generation).as_ref(), Some(word));
+        }
+        let draws = history.steps[1].passes[0].iter().filter_map(|a| a.draw);
+        assert_eq!(draws.count(), 1);
+
+        let origin = history.origin(2, 2).unwrap();
+        assert_eq!(origin.index, 1);
+        assert_eq!(origin.applications[0].production, Some(1));
+        assert_eq!(origin.applications[0].target.start, 2);
+        assert_eq!(history.origin(2, 0).unwrap().index, 0);
+        assert_eq!(history.ancestor(5, words[5].len() - 1), Some(0));
+
+        while system.step_back() {}
+        assert_eq!(system.generation, 0);
+        assert_eq!(system.axiom, words[0]);
+        system.step_forward();
+        system.step_forward();
+        assert_eq!(system.axiom, words[2]);
+
+        system.step_by(3);
+        assert_eq!(system.axiom, words[5]);
+        assert!(!system.step_forward());
+    }
+}
+
+#[test]
+fn test_limits() {
+    let mut system = LSystem::new("A".into());
+    system.register_rule("A".into(), || "AA".into());
+    system.seed(1);
+    system.limits.max_len = Some(20);
+    assert_eq!(system.try_step_by(10), Err(limits::LimitError::Length(20)));
+    assert_eq!(system.generation, 4);
+    assert_eq!(system.axiom.len(), 16);
+
+    let bytes = limits::word_bytes(&system.axiom);
+    system.limits = limits::Limits {
+        max_bytes: Some(bytes),
+        ..Default::default()
+    };
+    assert_eq!(system.try_step(), Err(limits::LimitError::Bytes(bytes)));
+    system.limits.max_bytes = None;
+    system.limits.max_duration = Some(std::time::Duration::ZERO);
+    assert!(matches!(
+        system.try_step_by(20),
+        Err(limits::LimitError::Duration(_))
+    ));
+    assert!(system.generation < 20);
+
+    system.limits = Default::default();
+    assert_eq!(system.try_step(), Ok(()));
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn test_parallel_step() {
+    let build = || {
+        let mut system = LSystem::new("A[B]C%".into());
+        system.register_rule("A".into(), || "AB[%C]".into());
+        system.register_rule("BC".into(), || "CAB".into());
+        system.register_rule("C".into(), || "AC".into());
+        system
+            .decomposition_rules
+            .push(Production::new("B".into(), || "BA".into()));
+        system
+    };
+    let (mut sequential, mut parallel) = (build(), build());
+    parallel.record(history::Recording::Diffs);
+    for chunk_len in [1, 2, 3, 7, 1000] {
+        sequential.step();
+        parallel.par_step(chunk_len);
+        assert_eq!(parallel.axiom, sequential.axiom);
+    }
+    let history = parallel.history.as_ref().unwrap();
+    assert_eq!(history.word(5).as_ref(), Some(&sequential.axiom));
+
+    let stochastic = |threads: usize| {
+        let mut system = LSystem::new("A".into());
+        system
+            .register_stochastic_rule("A".into(), vec![(1., "AB".into()), (1., "BA".into())])
+            .unwrap();
+        system.register_rule("B".into(), || "A".into());
+        system.seed(5);
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        pool.install(|| system.par_step_by(12, 4));
+        system.axiom
+    };
+    assert_eq!(stochastic(1), stochastic(4));
+
+    let mut system = LSystem::new("A".into());
+    system.register_rule("A".into(), || "AA".into());
+    system.limits.max_len = Some(5);
+    assert_eq!(
+        system.try_par_step_by(10, 2),
+        Err(limits::LimitError::Length(5))
+    );
+    assert_eq!(system.axiom, "AAAA");
+}
+
+// Productions are shared between threads, so they keep state in atomics.
+#[test]
+fn test_stateful_production() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let count = Arc::new(AtomicUsize::new(0));
+    let mut system = LSystem::new("AA".into());
+    let counter = count.clone();
+    system.register_rule("A".into(), move || {
+        format!("A{}", counter.fetch_add(1, Ordering::Relaxed) + 1)
+    });
+    system.step();
+    assert_eq!(system.axiom, "A1A2");
+    assert_eq!(count.load(Ordering::Relaxed), 2);
+}
+
+#[test]
+fn test_inference() {
+    let bounds = inference::Bounds {
+        max_successor_len: 3,
+        max_alphabet: 4,
+    };
+    let generations: Vec<Word> = ["A", "AB", "ABA", "ABAAB"]
+        .iter()
+        .map(|w| Word::from(*w))
+        .collect();
+    let found = inference::infer(&generations, bounds).unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].successors[&Symbol::from('A')], "AB");
+    assert_eq!(found[0].successors[&Symbol::from('B')], "A");
+
+    let generations = [Word::from("AB"), Word::from("ABBA")];
+    let found = inference::infer(&generations, bounds).unwrap();
+    assert_eq!(found.len(), 3);
+    for inferred in found {
+        let mut system = inferred.system();
+        system.step();
+        assert_eq!(system.axiom, "ABBA");
+    }
+
+    let small = inference::Bounds {
+        max_alphabet: 1,
+        ..bounds
+    };
+    assert_eq!(
+        inference::infer(&generations, small),
+        Err(inference::InferenceError::Alphabet(2))
+    );
+}
+
+#[test]
+fn test_step_until_stable() {
+    use stability::Stability;
+
+    let mut system = LSystem::new("AAB".into());
+    system.register_rule("A".into(), || "B".into());
+    assert_eq!(system.step_until_stable(10), Ok(Stability::FixedPoint(1)));
+    assert_eq!(system.generation, 2);
+    assert_eq!(system.axiom, "BBB");
+
+    let mut system = LSystem::new("XA".into());
+    system.register_rule("A".into(), || "B".into());
+    system.register_rule("B".into(), || "C".into());
+    system.register_rule("C".into(), || "A".into());
+    assert_eq!(
+        system.step_until_stable(10),
+        Ok(Stability::Cycle {
+            start: 0,
+            period: 3
+        })
+    );
+
+    let mut system = LSystem::new("A".into());
+    system.register_rule("A".into(), || "AB".into());
+    assert_eq!(system.step_until_stable(5), Ok(Stability::Unstable));
+    assert_eq!(system.generation, 5);
+    assert_ne!(
+        stability::word_hash(&Word::from("AB")),
+        stability::word_hash(&Word::from("BA"))
+    );
+}
+
+#[test]
+fn test_lint() {
+    use lint::Lint;
+
+    let mut system = LSystem::new("F[X".into());
+    for (token, successor) in [
+        ("X", "F[+X]Y"),
+        ("X", "F"),
+        ("Z", "F]"),
+        ("FX", "F"),
+        ("XF", "F"),
+    ] {
+        system.register_rule(token.into(), move || successor.into());
+    }
+    // Calling this one without a real match would panic.
+    system.register_match_rule("W".into(), |m: &Match| {
+        format!("W({})", m.params()[0].as_f64().unwrap() + 1.)
+    });
+    let mut executor = LSystemExecutor::new(0);
+    executor.register_rule("F".into(), |n: &mut i32| *n += 1);
+    executor.register_rule("FF".into(), |n: &mut i32| *n += 2);
+    for token in &["[", "]", "+"] {
+        executor.register_rule(token.to_string(), |_: &mut i32| {});
+    }
+
+    let lints = lint::lint(&system, &executor);
+    let expected = [
+        Lint::NotAnalysable("W".into()),
+        Lint::UnbalancedBrackets(None),
+        Lint::UnbalancedBrackets(Some("Z".into())),
+        Lint::Unhandled(Symbol::from('Y')),
+        Lint::Unreachable("Z".into()),
+        Lint::Unreachable("W".into()),
+        Lint::Shadowed {
+            token: "X".into(),
+            by: 0,
+        },
+        Lint::Overlap {
+            first: "FX".into(),
+            second: "XF".into(),
+        },
+        Lint::Overlap {
+            first: "XF".into(),
+            second: "FX".into(),
+        },
+        Lint::Shadowed {
+            token: "FF".into(),
+            by: 0,
+        },
+    ];
+    assert_eq!(lints, expected);
+}
+
+#[test]
+fn test_lsystem_macro() {
+    let mut system = lsystem! {
+        const LEN = 2 * 4;
+        axiom: F(LEN) X;
+        X -> [+X] [-X] : 0;
+        X -> F(LEN / 2) X : 1;
+        F(x) : x > 1 -> F(x * 0.5);
+    };
+    system.step_by(2);
+    assert_eq!(system.axiom, "F(2)F(2)F(4)X");
+
+    let unweighted = lsystem!(axiom: A; A -> AB; B -> A);
+    assert_eq!(unweighted.production_rules.len(), 2);
+
+    // Weighted productions are grouped as in the `.lsys` format, even with a
+    // constant between them.
+    let grouped = lsystem!(axiom: A; A -> B : 1; const K = 3; A -> C : K);
+    assert_eq!(grouped.production_rules.len(), 1);
+    assert_eq!(grouped.production_rules[0].successors.len(), 2);
+}
@@ -0,0 +1,102 @@
+use crate::word::{Module, Symbol, Word};
+use crate::{table_rules, Candidates, LSystem};
+use std::fmt;
+
+/// A terminal age that isn't positive, at which a module would be rewritten
+/// again and again without time passing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TerminalAgeError {
+    pub symbol: Symbol,
+    pub age: f64,
+}
+
+impl fmt::Display for TerminalAgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "the terminal age of `{}` is {}, but must be positive",
+            self.symbol, self.age
+        )
+    }
+}
+
+impl std::error::Error for TerminalAgeError {}
+
+impl LSystem {
+    /// Moves a timed system forward by `dt`, as in the dL-systems of
+    /// Prusinkiewicz and Lindenmayer. Every module ages by `dt`, and modules
+    /// that reach the terminal age of their symbol are rewritten by the
+    /// production matching at their position. The time past maturity carries
+    /// over to the successor modules, on top of whatever age the production
+    /// gave them, so they may mature within the same call.
+    ///
+    /// Productions come from the table of the current generation, and the
+    /// generation doesn't change. Fails without changing the system if a
+    /// terminal age isn't positive.
+    pub fn advance(&mut self, dt: f64) -> Result<(), TerminalAgeError> {
+        if let Some((symbol, age)) = self
+            .terminal_ages
+            .iter()
+            .find(|(_, age)| age.is_nan() || **age <= 0.)
+        {
+            return Err(TerminalAgeError {
+                symbol: *symbol,
+                age: *age,
+            });
+        }
+
+        self.axiom.iter_mut().for_each(|m| m.age += dt);
+        self.time += dt;
+
+        let table = self.table_for(self.generation);
+        let rules = table_rules(
+            &mut self.production_rules,
+            &mut self.tables,
+            table.as_deref(),
+        );
+        let candidates = Candidates::new(rules);
+        let terminal_ages = &self.terminal_ages;
+        let overflow = |module: &Module| {
+            let terminal_age = terminal_ages.get(&module.symbol)?;
+            Some(module.age - terminal_age).filter(|overflow| *overflow >= 0.)
+        };
+
+        loop {
+            let axiom = &self.axiom;
+            let mut new_axiom = Word(Vec::with_capacity(axiom.len()));
+            let mut rewritten_any = false;
+            let mut i = 0;
+
+            while i < axiom.len() {
+                let rewritten = match overflow(&axiom[i]) {
+                    Some(overflow) => candidates
+                        .rewrite(
+                            rules,
+                            axiom,
+                            i,
+                            &self.ignore,
+                            self.generation,
+                            &mut self.rng,
+                        )
+                        .map(|(successor, len)| (successor, len, overflow)),
+                    None => None,
+                };
+
+                if let Some((mut successor, len, overflow)) = rewritten {
+                    successor.iter_mut().for_each(|m| m.age += overflow);
+                    new_axiom.extend(successor.0);
+                    rewritten_any = true;
+                    i += len;
+                } else {
+                    new_axiom.push(axiom[i].clone());
+                    i += 1;
+                }
+            }
+
+            self.axiom = new_axiom;
+            if !rewritten_any {
+                return Ok(());
+            }
+        }
+    }
+}
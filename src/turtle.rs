@@ -31,14 +31,15 @@ pub use web::*;
 #[cfg(feature = "web-sys")]
 mod web {
     use super::*;
-    use wasm_bindgen::JsValue;
     use web_sys::CanvasRenderingContext2d;
 
+    type Snapshot = (f64, (f64, f64), f64, (f64, f64, f64));
+
     pub struct CanvasContextTurtle {
         angle: f64,
         pos: (f64, f64),
         color: (f64, f64, f64),
-        stack: Vec<(f64, (f64, f64), f64, (f64, f64, f64))>,
+        stack: Vec<Snapshot>,
         line_width: f64,
         context: CanvasRenderingContext2d,
     }
@@ -59,10 +60,10 @@ mod web {
         ) -> Self {
             context.begin_path();
             context.set_line_width(line_width);
-            context.set_stroke_style(&JsValue::from_str(&format!(
+            context.set_stroke_style_str(&format!(
                 "rgb({}, {}, {})",
                 color.0, color.1, color.2
-            )));
+            ));
             context.move_to(pos.0, pos.1);
 
             Self {
@@ -120,7 +121,8 @@ mod web {
 
         fn flush(&mut self) -> Result<(), Self::Error> {
             self.context.close_path();
-            Ok(self.context.stroke())
+            self.context.stroke();
+            Ok(())
         }
     }
 
@@ -130,10 +132,10 @@ mod web {
         }
         fn set_color(&mut self, color: (f64, f64, f64)) {
             self.reset_path();
-            self.context.set_stroke_style(&JsValue::from_str(&format!(
+            self.context.set_stroke_style_str(&format!(
                 "rgb({}, {}, {})",
                 color.0, color.1, color.2
-            )));
+            ));
             self.color = color;
         }
         fn scale_color(&mut self, (r, g, b): (f64, f64, f64)) {
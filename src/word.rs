@@ -0,0 +1,325 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::iter::FromIterator;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Mutex, OnceLock};
+
+// Single character symbols are identified by their code point, so only longer
+// names need to go through the interner. Their ids start past `char::MAX`.
+const FIRST_INTERNED: u32 = char::MAX as u32 + 1;
+
+#[derive(Default)]
+struct Interner {
+    ids: HashMap<&'static str, u32>,
+    names: Vec<&'static str>,
+}
+
+fn interner() -> &'static Mutex<Interner> {
+    static INTERNER: OnceLock<Mutex<Interner>> = OnceLock::new();
+    INTERNER.get_or_init(Default::default)
+}
+
+/// An interned symbol name. Symbols are cheap to copy and compare.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+impl Symbol {
+    pub fn new(name: &str) -> Self {
+        let mut chars = name.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            return Self::from_char(c);
+        }
+
+        let mut interner = interner().lock().unwrap();
+        if let Some(id) = interner.ids.get(name) {
+            return Self(*id);
+        }
+        let name: &'static str = Box::leak(name.to_owned().into_boxed_str());
+        let id = FIRST_INTERNED + interner.names.len() as u32;
+        interner.names.push(name);
+        interner.ids.insert(name, id);
+        Self(id)
+    }
+
+    pub const fn from_char(c: char) -> Self {
+        Self(c as u32)
+    }
+
+    pub fn as_char(self) -> Option<char> {
+        char::from_u32(self.0)
+    }
+
+    pub fn name(self) -> String {
+        self.to_string()
+    }
+
+    fn interned_name(self) -> &'static str {
+        interner().lock().unwrap().names[(self.0 - FIRST_INTERNED) as usize]
+    }
+
+    pub(crate) fn first_char(self) -> Option<char> {
+        self.as_char()
+            .or_else(|| self.interned_name().chars().next())
+    }
+
+    /// Strips this symbol's name off the front of `text`.
+    pub(crate) fn strip_from(self, text: &str) -> Option<&str> {
+        match self.as_char() {
+            Some(c) => text.strip_prefix(c),
+            None => text.strip_prefix(self.interned_name()),
+        }
+    }
+}
+
+impl From<char> for Symbol {
+    fn from(c: char) -> Self {
+        Self::from_char(c)
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Self {
+        Self::new(name)
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.as_char() {
+            Some(c) => write!(f, "{}", c),
+            None => f.write_str(self.interned_name()),
+        }
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Symbol({:?})", self.to_string())
+    }
+}
+
+pub(crate) const PUSH: Symbol = Symbol::from_char('[');
+pub(crate) const POP: Symbol = Symbol::from_char(']');
+
+/// A symbol along with its parameters, written `F(1.5, [0, 1])`. Modules are
+/// compared by symbol and parameters, not age.
+#[derive(Clone, Debug)]
+pub struct Module {
+    pub symbol: Symbol,
+    pub params: Vec<Value>,
+    /// Time since the module was created, see [`crate::LSystem::advance`].
+    pub age: f64,
+}
+
+impl Module {
+    pub fn new(symbol: impl Into<Symbol>, params: Vec<Value>) -> Self {
+        Self {
+            symbol: symbol.into(),
+            params,
+            age: 0.,
+        }
+    }
+
+    /// The parameters as numbers, if they all are.
+    pub fn numeric_params(&self) -> Option<Vec<f64>> {
+        self.params.iter().map(Value::as_f64).collect()
+    }
+}
+
+impl PartialEq for Module {
+    fn eq(&self, other: &Self) -> bool {
+        self.symbol == other.symbol && self.params == other.params
+    }
+}
+
+impl From<Symbol> for Module {
+    fn from(symbol: Symbol) -> Self {
+        Self::new(symbol, vec![])
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.symbol)?;
+        if self.params.is_empty() {
+            return Ok(());
+        }
+
+        f.write_str("(")?;
+        for (i, param) in self.params.iter().enumerate() {
+            if i != 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}", param)?;
+        }
+        f.write_str(")")
+    }
+}
+
+/// Converts a number to a parameter, keeping integral values as integers so
+/// that they can still be read back as one.
+pub fn number(n: f64) -> Value {
+    if n.fract() == 0. && n.abs() < i64::MAX as f64 {
+        Value::from(n as i64)
+    } else {
+        Value::from(n)
+    }
+}
+
+/// A string of modules. Every character of the string syntax is a symbol, and
+/// a parenthesised list of JSON values right after one is its parameters.
+#[derive(Clone, Default, PartialEq)]
+pub struct Word(pub Vec<Module>);
+
+impl Word {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut modules = vec![];
+        let mut rest = text;
+
+        while let Some(c) = rest.chars().next() {
+            rest = &rest[c.len_utf8()..];
+            let mut module = Module::from(Symbol::from(c));
+            if let Some((params, len)) = parse_params(rest) {
+                module.params = params;
+                rest = &rest[len..];
+            }
+            modules.push(module);
+        }
+
+        Self(modules)
+    }
+}
+
+// Reads the parameter list at the start of `text`, returning it along with the
+// number of bytes it spans.
+fn parse_params(text: &str) -> Option<(Vec<Value>, usize)> {
+    if !text.starts_with('(') {
+        return None;
+    }
+
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            _ if in_string => {}
+            '(' | '[' | '{' => depth += 1,
+            ']' | '}' => depth -= 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    let params = serde_json::from_str(&format!("[{}]", &text[1..i])).ok()?;
+                    return Some((params, i + 1));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Matches `token` against the names of the modules at the start of `modules`,
+/// returning how many modules it spans. Only the last of them may carry
+/// parameters.
+pub fn match_token(modules: &[Module], token: &str) -> Option<usize> {
+    let mut rest = token;
+    let mut len = 0;
+
+    while !rest.is_empty() {
+        if len > 0 && !modules[len - 1].params.is_empty() {
+            return None;
+        }
+        rest = modules.get(len)?.symbol.strip_from(rest)?;
+        len += 1;
+    }
+
+    Some(len).filter(|len| *len > 0)
+}
+
+impl Deref for Word {
+    type Target = Vec<Module>;
+
+    fn deref(&self) -> &Vec<Module> {
+        &self.0
+    }
+}
+
+impl DerefMut for Word {
+    fn deref_mut(&mut self) -> &mut Vec<Module> {
+        &mut self.0
+    }
+}
+
+impl From<Vec<Module>> for Word {
+    fn from(modules: Vec<Module>) -> Self {
+        Self(modules)
+    }
+}
+
+impl From<&str> for Word {
+    fn from(text: &str) -> Self {
+        Self::parse(text)
+    }
+}
+
+impl From<String> for Word {
+    fn from(text: String) -> Self {
+        Self::parse(&text)
+    }
+}
+
+impl FromIterator<Module> for Word {
+    fn from_iter<I: IntoIterator<Item = Module>>(modules: I) -> Self {
+        Self(modules.into_iter().collect())
+    }
+}
+
+impl fmt::Display for Word {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.iter().try_for_each(|m| write!(f, "{}", m))
+    }
+}
+
+impl fmt::Debug for Word {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Word({:?})", self.to_string())
+    }
+}
+
+impl PartialEq<str> for Word {
+    // Compares against the string syntax without building the whole string.
+    fn eq(&self, other: &str) -> bool {
+        struct Remaining<'a>(&'a str);
+
+        impl fmt::Write for Remaining<'_> {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                self.0 = self.0.strip_prefix(s).ok_or(fmt::Error)?;
+                Ok(())
+            }
+        }
+
+        let mut remaining = Remaining(other);
+        fmt::write(&mut remaining, format_args!("{}", self)).is_ok() && remaining.0.is_empty()
+    }
+}
+
+impl PartialEq<&str> for Word {
+    fn eq(&self, other: &&str) -> bool {
+        *self == **other
+    }
+}
+
+impl PartialEq<String> for Word {
+    fn eq(&self, other: &String) -> bool {
+        *self == **other
+    }
+}
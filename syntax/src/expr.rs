@@ -0,0 +1,390 @@
+use std::fmt;
+
+/// Arithmetic over the parameters of a module. Comparisons and logical
+/// operators produce `1.` for true and `0.` for false, and any nonzero value
+/// counts as true.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    Variable(usize),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Call(Function, Vec<Expr>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Function {
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan,
+    Atan2,
+    Sqrt,
+    Abs,
+    Exp,
+    Ln,
+    Floor,
+    Ceil,
+    Min,
+    Max,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "sin" => Self::Sin,
+            "cos" => Self::Cos,
+            "tan" => Self::Tan,
+            "asin" => Self::Asin,
+            "acos" => Self::Acos,
+            "atan" => Self::Atan,
+            "atan2" => Self::Atan2,
+            "sqrt" => Self::Sqrt,
+            "abs" => Self::Abs,
+            "exp" => Self::Exp,
+            "ln" => Self::Ln,
+            "floor" => Self::Floor,
+            "ceil" => Self::Ceil,
+            "min" => Self::Min,
+            "max" => Self::Max,
+            _ => return None,
+        })
+    }
+
+    fn arity(self) -> usize {
+        match self {
+            Self::Atan2 | Self::Min | Self::Max => 2,
+            _ => 1,
+        }
+    }
+
+    fn apply(self, args: &[f64]) -> f64 {
+        match self {
+            Self::Sin => args[0].sin(),
+            Self::Cos => args[0].cos(),
+            Self::Tan => args[0].tan(),
+            Self::Asin => args[0].asin(),
+            Self::Acos => args[0].acos(),
+            Self::Atan => args[0].atan(),
+            Self::Atan2 => args[0].atan2(args[1]),
+            Self::Sqrt => args[0].sqrt(),
+            Self::Abs => args[0].abs(),
+            Self::Exp => args[0].exp(),
+            Self::Ln => args[0].ln(),
+            Self::Floor => args[0].floor(),
+            Self::Ceil => args[0].ceil(),
+            Self::Min => args[0].min(args[1]),
+            Self::Max => args[0].max(args[1]),
+        }
+    }
+}
+
+fn truth(value: bool) -> f64 {
+    if value {
+        1.
+    } else {
+        0.
+    }
+}
+
+impl Expr {
+    /// Parses `source`, resolving identifiers to their index in `variables`.
+    pub fn parse(source: &str, variables: &[String]) -> Result<Self, ParseError> {
+        let mut parser = Parser::new(source);
+        let expr = parser.expr(variables)?;
+        parser.skip_whitespace();
+        if !parser.is_done() {
+            return Err(parser.error("expected an operator"));
+        }
+        Ok(expr)
+    }
+
+    pub fn eval(&self, args: &[f64]) -> f64 {
+        match self {
+            Self::Number(n) => *n,
+            Self::Variable(i) => args[*i],
+            Self::Unary(UnaryOp::Neg, e) => -e.eval(args),
+            Self::Unary(UnaryOp::Not, e) => truth(e.eval(args) == 0.),
+            Self::Binary(op, a, b) => {
+                let a = a.eval(args);
+                match op {
+                    BinaryOp::And => return truth(a != 0. && b.eval(args) != 0.),
+                    BinaryOp::Or => return truth(a != 0. || b.eval(args) != 0.),
+                    _ => {}
+                }
+
+                let b = b.eval(args);
+                match op {
+                    BinaryOp::Add => a + b,
+                    BinaryOp::Sub => a - b,
+                    BinaryOp::Mul => a * b,
+                    BinaryOp::Div => a / b,
+                    BinaryOp::Rem => a % b,
+                    BinaryOp::Pow => a.powf(b),
+                    BinaryOp::Lt => truth(a < b),
+                    BinaryOp::Le => truth(a <= b),
+                    BinaryOp::Gt => truth(a > b),
+                    BinaryOp::Ge => truth(a >= b),
+                    BinaryOp::Eq => truth(a == b),
+                    BinaryOp::Ne => truth(a != b),
+                    BinaryOp::And | BinaryOp::Or => unreachable!(),
+                }
+            }
+            Self::Call(f, e) => f.apply(&e.iter().map(|e| e.eval(args)).collect::<Vec<_>>()),
+        }
+    }
+
+    pub fn is_true(&self, args: &[f64]) -> bool {
+        self.eval(args) != 0.
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub message: String,
+    /// Byte offset into the parsed source.
+    pub position: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}", self.message, self.position)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// Used by the parsers built on top of it, but not part of the API the
+// `l-system` crate re-exports.
+#[doc(hidden)]
+pub struct Parser<'a> {
+    source: &'a str,
+    pub position: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            position: 0,
+        }
+    }
+
+    pub fn rest(&self) -> &'a str {
+        &self.source[self.position..]
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.position == self.source.len()
+    }
+
+    pub fn error(&self, message: impl Into<String>) -> ParseError {
+        ParseError {
+            message: message.into(),
+            position: self.position,
+        }
+    }
+
+    pub fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    pub fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.rest().chars().next()
+    }
+
+    pub fn next_char(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += c.len_utf8();
+        Some(c)
+    }
+
+    pub fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(token) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn expect(&mut self, token: &str) -> Result<(), ParseError> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected `{}`", token)))
+        }
+    }
+
+    pub fn identifier(&mut self) -> Option<&'a str> {
+        self.skip_whitespace();
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        if len == 0 || rest.starts_with(|c: char| c.is_ascii_digit()) {
+            return None;
+        }
+        self.position += len;
+        Some(&rest[..len])
+    }
+
+    pub fn number(&mut self) -> Option<f64> {
+        self.skip_whitespace();
+        let rest = self.rest();
+        let mut len = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        if rest[len..].starts_with(['e', 'E']) {
+            let exponent = &rest[len + 1..];
+            let sign = usize::from(exponent.starts_with(['+', '-']));
+            let digits = exponent[sign..]
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(exponent.len() - sign);
+            if digits > 0 {
+                len += 1 + sign + digits;
+            }
+        }
+        let number = rest[..len].parse().ok()?;
+        self.position += len;
+        Some(number)
+    }
+
+    pub fn expr(&mut self, variables: &[String]) -> Result<Expr, ParseError> {
+        self.binary(0, variables)
+    }
+
+    fn binary_op(&mut self, level: usize) -> Option<BinaryOp> {
+        const LEVELS: &[&[(&str, BinaryOp)]] = &[
+            &[("||", BinaryOp::Or)],
+            &[("&&", BinaryOp::And)],
+            &[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne)],
+            &[
+                ("<=", BinaryOp::Le),
+                (">=", BinaryOp::Ge),
+                ("<", BinaryOp::Lt),
+                (">", BinaryOp::Gt),
+            ],
+            &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+            &[
+                ("*", BinaryOp::Mul),
+                ("/", BinaryOp::Div),
+                ("%", BinaryOp::Rem),
+            ],
+        ];
+
+        // `->` separates a guard from its successor and is never a subtraction
+        if self.rest().trim_start().starts_with("->") {
+            return None;
+        }
+
+        LEVELS[level]
+            .iter()
+            .find(|(token, _)| self.eat(token))
+            .map(|(_, op)| *op)
+    }
+
+    fn binary(&mut self, level: usize, variables: &[String]) -> Result<Expr, ParseError> {
+        if level == 6 {
+            return self.unary(variables);
+        }
+
+        let mut lhs = self.binary(level + 1, variables)?;
+        while let Some(op) = self.binary_op(level) {
+            let rhs = self.binary(level + 1, variables)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self, variables: &[String]) -> Result<Expr, ParseError> {
+        if self.eat("-") {
+            Ok(Expr::Unary(UnaryOp::Neg, Box::new(self.unary(variables)?)))
+        } else if self.eat("!") {
+            Ok(Expr::Unary(UnaryOp::Not, Box::new(self.unary(variables)?)))
+        } else {
+            let base = self.primary(variables)?;
+            if self.eat("^") {
+                let exponent = self.unary(variables)?;
+                Ok(Expr::Binary(
+                    BinaryOp::Pow,
+                    Box::new(base),
+                    Box::new(exponent),
+                ))
+            } else {
+                Ok(base)
+            }
+        }
+    }
+
+    fn primary(&mut self, variables: &[String]) -> Result<Expr, ParseError> {
+        if self.eat("(") {
+            let expr = self.expr(variables)?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+        if let Some(number) = self.number() {
+            return Ok(Expr::Number(number));
+        }
+
+        let start = self.position;
+        let name = self
+            .identifier()
+            .ok_or_else(|| self.error("expected an expression"))?;
+        if let Some(i) = variables.iter().position(|v| v == name) {
+            return Ok(Expr::Variable(i));
+        }
+
+        let function = Function::from_name(name).ok_or_else(|| ParseError {
+            message: format!("unknown variable `{}`", name),
+            position: start,
+        })?;
+        self.expect("(")?;
+        let mut args = vec![];
+        if !self.eat(")") {
+            loop {
+                args.push(self.expr(variables)?);
+                if self.eat(")") {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+        if args.len() != function.arity() {
+            return Err(ParseError {
+                message: format!("`{}` takes {} arguments", name, function.arity()),
+                position: start,
+            });
+        }
+        Ok(Expr::Call(function, args))
+    }
+}
@@ -0,0 +1,5 @@
+//! The expressions and productions of the `.lsys` format, parsed the same way
+//! by the `l-system` crate and by its `lsystem!` macro as it checks a grammar.
+
+pub mod expr;
+pub mod rule;
@@ -0,0 +1,164 @@
+use crate::expr::{Expr, ParseError, Parser};
+use std::fmt;
+
+/// A module list such as `A(x*0.5, y+1) F(x)`, giving each symbol along with
+/// the expressions of its parameters. Whitespace between modules is
+/// insignificant, and the list runs to the end of the parser.
+pub fn modules(
+    parser: &mut Parser,
+    variables: &[String],
+) -> Result<Vec<(char, Vec<Expr>)>, ParseError> {
+    let mut modules = vec![];
+    while let Some(symbol) = parser.next_char() {
+        let mut args = vec![];
+        if parser.eat("(") {
+            loop {
+                args.push(parser.expr(variables)?);
+                if parser.eat(")") {
+                    break;
+                }
+                parser.expect(",")?;
+            }
+        }
+        modules.push((symbol, args));
+    }
+    Ok(modules)
+}
+
+/// Parses a token along with the names of its parameters, as in `A(x, y)`.
+pub fn predecessor(parser: &mut Parser) -> Result<(String, Vec<String>), ParseError> {
+    parser.skip_whitespace();
+    let rest = parser.rest();
+    let len = rest
+        .find(|c: char| c.is_whitespace() || c == '(' || c == ':')
+        .unwrap_or(rest.len());
+    let len = rest[..len].find("->").unwrap_or(len);
+    if len == 0 {
+        return Err(parser.error("expected a predecessor"));
+    }
+    let token = rest[..len].to_string();
+    parser.position += len;
+
+    let mut parameters = vec![];
+    if parser.eat("(") {
+        loop {
+            let name = parser
+                .identifier()
+                .ok_or_else(|| parser.error("expected a parameter name"))?;
+            parameters.push(name.to_string());
+            if parser.eat(")") {
+                break;
+            }
+            parser.expect(",")?;
+        }
+    }
+    Ok((token, parameters))
+}
+
+/// The parts of a parametric production, as written in its source.
+pub struct Rule {
+    pub token: String,
+    pub parameters: Vec<String>,
+    pub condition: Option<Expr>,
+    pub weight: Option<Expr>,
+    pub modules: Vec<(char, Vec<Expr>)>,
+}
+
+impl Rule {
+    /// Parses `TOKEN(params) : guard -> successor`, where the expressions may
+    /// also use `constants`, which come after the parameters. If `weighted`, a
+    /// successor ending in `: expr`, with `expr` only using constants, gives
+    /// the weight of a stochastic production.
+    pub fn parse(rule: &str, constants: &[String], weighted: bool) -> Result<Self, ParseError> {
+        let mut parser = Parser::new(rule);
+        let (token, parameters) = predecessor(&mut parser)?;
+        let variables: Vec<String> = parameters.iter().chain(constants).cloned().collect();
+
+        let condition = if parser.eat(":") {
+            Some(parser.expr(&variables)?)
+        } else {
+            None
+        };
+        parser.expect("->")?;
+
+        let start = parser.position;
+        let weight = match rule.rfind(':').filter(|i| weighted && *i >= start) {
+            Some(i) => {
+                let weight = Expr::parse(&rule[i + 1..], constants).map_err(|e| ParseError {
+                    position: i + 1 + e.position,
+                    ..e
+                })?;
+                Some((i, weight))
+            }
+            None => None,
+        };
+        let modules = match &weight {
+            Some((end, _)) => {
+                let successor = &rule[..*end];
+                let mut parser = Parser::new(successor);
+                parser.position = start;
+                let modules = modules(&mut parser, &variables)?;
+                if !parser.is_done() {
+                    return Err(parser.error("expected a module"));
+                }
+                modules
+            }
+            None => modules(&mut parser, &variables)?,
+        };
+
+        Ok(Self {
+            token,
+            parameters,
+            condition,
+            weight: weight.map(|(_, w)| w),
+            modules,
+        })
+    }
+
+    /// Whether this rule is another successor of the stochastic production
+    /// `first` starts. Consecutive weighted rules with the same token, number
+    /// of parameters and guard make up one production.
+    pub fn continues(&self, first: &Rule) -> bool {
+        first.weight.is_some()
+            && self.weight.is_some()
+            && self.token == first.token
+            && self.parameters.len() == first.parameters.len()
+            && self.condition == first.condition
+    }
+}
+
+/// Weights that a stochastic production can't pick a successor with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WeightError {
+    /// A weight that is negative or NaN.
+    Invalid(f64),
+    /// Weights that are all zero.
+    ZeroTotal,
+}
+
+impl fmt::Display for WeightError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Invalid(weight) => write!(f, "the weight {} is negative or NaN", weight),
+            Self::ZeroTotal => f.write_str("the weights are all zero"),
+        }
+    }
+}
+
+impl std::error::Error for WeightError {}
+
+/// Checks that the weights of a stochastic production are numbers of at least
+/// zero and not all zero.
+pub fn check_weights(weights: impl IntoIterator<Item = f64>) -> Result<(), WeightError> {
+    let mut total = 0.;
+    for weight in weights {
+        if weight.is_nan() || weight < 0. {
+            return Err(WeightError::Invalid(weight));
+        }
+        total += weight;
+    }
+    if total == 0. {
+        return Err(WeightError::ZeroTotal);
+    }
+    Ok(())
+}